@@ -4,7 +4,7 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     if let Ok(_) = env::var("CARGO_FEATURE_PROST") {
         let mut config = prost_build::Config::new();
         config.extern_path(".flux.Thing", "crate::prelude::Thing");
-        //config.extern_path(".flux.Dynamic", "crate::prelude::Dynamic");
+        config.extern_path(".flux.Dynamic", "crate::dynamic::Dynamic");
 
         let attribute = "#[derive(documented::Documented, serde::Serialize, serde::Deserialize)]";
         if let Ok(_) = env::var("CARGO_FEATURE_TONIC") {
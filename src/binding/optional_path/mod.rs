@@ -7,6 +7,7 @@ pub use error::*;
 mod parse;
 use parse::*;
 mod access;
+pub use access::Access;
 use access::*;
 
 use derive_more::derive::{Display, From};
@@ -114,13 +115,13 @@ impl OptionalParsedPath {
     }
 //}
 //impl<'a> ReflectPath<'a> for &'a OptionalParsedPath {
-    fn reflect_element<'a>(self, mut root: &dyn PartialReflect) -> PathResult<'a, &dyn PartialReflect> {
+    pub fn reflect_element<'a>(self, mut root: &dyn PartialReflect) -> PathResult<'a, &dyn PartialReflect> {
         for OffsetAccess { access, offset } in &self.0 {
             root = access.element(root, *offset)?;
         }
         Ok(root)
     }
-    fn reflect_element_mut<'a>(
+    pub fn reflect_element_mut<'a>(
         self,
         mut root: &mut dyn PartialReflect,
     ) -> PathResult<'a, &mut dyn PartialReflect> {
@@ -408,7 +408,9 @@ fn system(world: &mut World) {
 
 }
 
-#[derive(Component, Clone)]
+/// Reflected so replication can map its source and target entities to network ids.
+/// The entity functions are local to each app and aren't reflected.
+#[derive(Component, Clone, Reflect)]
 pub enum Binding {
     Path(PathBinding),
     List(ListBinding)
@@ -449,7 +451,7 @@ impl Binding {
     }
 }
 
-#[derive(Component, Clone)]
+#[derive(Component, Clone, Reflect)]
 pub struct PathBinding {
     pub source_entity: Option<Entity>,
     pub source_component_name: String,
@@ -457,6 +459,7 @@ pub struct PathBinding {
     pub target_entity: Option<Entity>,
     pub target_component_name: String,
     pub target_property_path: Option<String>,
+    #[reflect(ignore)]
     pub entity_func: Option<SetPropertyFunc>
 }
 
@@ -482,7 +485,7 @@ fn entity_to_string(entity: &Option<Entity>) -> String {
     }
 }
 
-#[derive(Component, Clone)]
+#[derive(Component, Clone, Reflect)]
 pub struct ListBinding {
     pub source_entity: Option<Entity>,
     pub source_component_name: String,
@@ -490,6 +493,7 @@ pub struct ListBinding {
     pub target_entity: Option<Entity>,
     pub target_component_name: String,
     pub target_property_path: Option<String>,
+    #[reflect(ignore)]
     pub create_entity_func: Option<CreateEntityFunc>
 }
 
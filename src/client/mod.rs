@@ -1,6 +1,11 @@
 use bevy::prelude::*;
 use crate::prelude::*;
 
+#[cfg(all(feature = "futures", feature = "tokio"))]
+mod websocket;
+#[cfg(all(feature = "futures", feature = "tokio"))]
+pub use websocket::*;
+
 pub struct FluxClientPlugin {
     config: FluxConfig
 }
@@ -16,5 +21,8 @@ impl FluxClientPlugin {
 impl Plugin for FluxClientPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((FluxPlugin::new(self.config.clone())));
+
+        #[cfg(all(feature = "futures", feature = "tokio"))]
+        app.add_systems(OnEnter(DbState::Connected), connect_to_server);
     }
 }
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use bevy::prelude::*;
+use bevy_wasm_tasks::Tasks;
+use futures::lock::Mutex;
+use futures_timer::Delay;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite_wasm::{Message, WebSocketStream};
+use crate::prelude::*;
+
+/// A client's end of a WebSocket connected to a server's `/ws` route.
+pub struct WsClientTransport {
+    sink: Mutex<SplitSink<WebSocketStream, Message>>,
+    stream: Mutex<SplitStream<WebSocketStream>>,
+}
+
+impl Transport for WsClientTransport {
+    type Address = String;
+
+    async fn connect(address: Self::Address) -> Result<Self> {
+        let (sink, stream) = tokio_tungstenite_wasm::connect(address).await?.split();
+        Ok(Self {
+            sink: Mutex::new(sink),
+            stream: Mutex::new(stream),
+        })
+    }
+
+    async fn send(&self, frame: Vec<u8>) -> Result<()> {
+        self.sink.lock().await.send(Message::Binary(frame.into())).await?;
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Option<Vec<u8>>> {
+        let mut stream = self.stream.lock().await;
+        while let Some(message) = stream.next().await {
+            match message? {
+                Message::Binary(data) => return Ok(Some(data.to_vec())),
+                Message::Text(text) => return Ok(Some(text.as_bytes().to_vec())),
+                Message::Close(_) => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// How long the client waits before reconnecting after its connection to the server drops.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Connects the session to the server's WebSocket route once the session exists, reconnecting
+/// whenever the connection drops. Each reconnect offers the server a resume token, so events sent
+/// during a brief outage are replayed if the server's channels have a grace period, and events lost
+/// in flight are resent if the server's route is reliable too.
+pub fn connect_to_server(session: Res<Session>, config: Res<FluxConfig>, tasks: Tasks) {
+    let session = session.clone();
+    let url = format!("{}?peer_id={}", config.get_ws_url(), session.get_id());
+    let options = BridgeOptions::default()
+        .with_reliable(ReliableLink::new())
+        .with_resume(ResumeHandle::default());
+
+    tasks.spawn_auto(async move |_| {
+        loop {
+            match WsClientTransport::connect(url.clone()).await {
+                Ok(transport) => {
+                    if let Err(err) = session.bridge(transport, options.clone()).await {
+                        warn!("Connection to server closed with an error: {}", err);
+                    }
+                },
+                Err(err) => warn!("Failed to connect to server: {}", err),
+            }
+            Delay::new(RECONNECT_DELAY).await;
+        }
+    });
+}
@@ -1,11 +1,15 @@
 use bevy::reflect::*;
 use bevy::prelude::*;
 use prost::Message;
+use prost::DecodeError;
 use prost::encoding::WireType;
 use prost::encoding::DecodeContext;
+use prost::encoding::{bytes, encoded_len_varint, key_len, string};
 use prost::Name;
 use prost_types::Any;
 use prost::encoding::skip_field;
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
 
 pub trait ReflectMessage: Reflect + Message {
     fn encode_boxed(&self) -> Vec<u8>;
@@ -13,29 +17,51 @@ pub trait ReflectMessage: Reflect + Message {
     fn full_name(&self) -> String;
 
     fn type_url(&self) -> String;
-}
-
-#[derive(Reflect, Debug)]
-pub struct Empty {
 
+    fn clone_boxed(&self) -> Box<dyn ReflectMessage>;
 }
 
-impl ReflectMessage for Empty {
+impl<T> ReflectMessage for T where T: Reflect + Message + Name + Clone {
+    fn encode_boxed(&self) -> Vec<u8> {
+        self.encode_to_vec()
+    }
+
     fn full_name(&self) -> String {
-        todo!()
+        <T as Name>::full_name()
     }
 
     fn type_url(&self) -> String {
-        todo!()
+        <T as Name>::type_url()
     }
-    
-    fn encode_boxed(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        self.encode(&mut buf).unwrap();
-        buf
+
+    fn clone_boxed(&self) -> Box<dyn ReflectMessage> {
+        Box::new(self.clone())
     }
 }
 
+type DecodeMessageFn = fn(&[u8]) -> Result<Box<dyn ReflectMessage>, DecodeError>;
+
+/// Decoders for the messages a `Dynamic` can hold, keyed by type URL.
+static MESSAGE_DECODERS: LazyLock<RwLock<HashMap<String, DecodeMessageFn>>> = LazyLock::new(|| {
+    let mut decoders: HashMap<String, DecodeMessageFn> = HashMap::new();
+    decoders.insert(<Empty as Name>::type_url(), decode_message::<Empty>);
+    RwLock::new(decoders)
+});
+
+fn decode_message<T>(buf: &[u8]) -> Result<Box<dyn ReflectMessage>, DecodeError> where T: ReflectMessage + Name + Default {
+    Ok(Box::new(T::decode(buf)?))
+}
+
+#[derive(Reflect, Debug, Clone, Default)]
+pub struct Empty {
+
+}
+
+impl Name for Empty {
+    const NAME: &'static str = "Empty";
+    const PACKAGE: &'static str = "flux";
+}
+
 impl Message for Empty {
     fn encoded_len(&self) -> usize {
         0
@@ -62,15 +88,25 @@ impl Message for Empty {
     }
 }
 
+/// Wraps any registered reflected prost message. On the wire it's encoded like a `google.protobuf.Any`,
+/// with the message's type URL used to pick the type to decode into.
 #[derive(Debug, TypePath)]
 pub struct Dynamic {
     pub value: Box<dyn ReflectMessage>,
+    /// Fields read so far while decoding, since the type URL and value may arrive in either order.
+    pending: Any,
     //pub cloned_func: Arc<dyn CloneBoxedCloneFunc>
 }
 
 impl Default for Dynamic {
     fn default() -> Self {
-        Self { value: Box::new(Empty {}) }
+        Self { value: Box::new(Empty {}), pending: Any::default() }
+    }
+}
+
+impl Clone for Dynamic {
+    fn clone(&self) -> Self {
+        Self::from_reflect(self.value.clone_boxed())
     }
 }
 
@@ -87,7 +123,7 @@ impl Message for Dynamic {
 
         let any = Any { type_url, value };
         
-        any.encode(buf).unwrap();
+        any.encode_raw(buf);
     }
 
     fn merge_field(
@@ -100,16 +136,35 @@ impl Message for Dynamic {
     where
         Self: Sized {
 
-        //self.value.as_ref().merge(buf)
-        todo!()
+        match tag {
+            1 => string::merge(wire_type, &mut self.pending.type_url, buf, ctx)?,
+            2 => bytes::merge(wire_type, &mut self.pending.value, buf, ctx)?,
+            _ => return skip_field(wire_type, tag, buf, ctx),
+        }
+
+        // Decode as soon as the type is known, and again if the value arrives afterwards
+        if !self.pending.type_url.is_empty() {
+            self.value = Self::decode_value(&self.pending)?;
+        }
+        Ok(())
     }
 
     fn encoded_len(&self) -> usize {
-        todo!()
+        let type_url = self.value.type_url();
+        let value_len = self.value.encoded_len();
+
+        let mut len = 0;
+        if !type_url.is_empty() {
+            len += string::encoded_len(1, &type_url);
+        }
+        if value_len > 0 {
+            len += key_len(2) + encoded_len_varint(value_len as u64) + value_len;
+        }
+        len
     }
 
     fn clear(&mut self) {
-        todo!()
+        *self = Self::default();
     }
 }
 
@@ -131,35 +186,35 @@ impl PartialReflect for Dynamic {
     }
     
     fn try_into_reflect(self: Box<Self>) -> Result<Box<dyn Reflect>, Box<dyn PartialReflect>> {
-        todo!()
+        Ok(self.value.into_reflect())
     }
     
     fn try_as_reflect(&self) -> Option<&dyn Reflect> {
-        todo!()
+        Some(self.value.as_reflect())
     }
     
     fn try_as_reflect_mut(&mut self) -> Option<&mut dyn Reflect> {
-        todo!()
+        Some(self.value.as_reflect_mut())
     }
     
     fn try_apply(&mut self, value: &dyn PartialReflect) -> Result<(), ApplyError> {
-        todo!()
+        self.value.try_apply(value)
     }
     
     fn reflect_ref(&self) -> ReflectRef {
-        todo!()
+        self.value.reflect_ref()
     }
     
     fn reflect_mut(&mut self) -> ReflectMut {
-        todo!()
+        self.value.reflect_mut()
     }
     
     fn reflect_owned(self: Box<Self>) -> ReflectOwned {
-        todo!()
+        self.value.reflect_owned()
     }
     
     fn clone_value(&self) -> Box<dyn PartialReflect> {
-        todo!()
+        self.value.clone_value()
     }
     /*
     fn get_represented_type_info(&self) -> Option<&'static TypeInfo> {
@@ -227,8 +282,20 @@ impl FromReflect for Dynamic {
 } */
 
 impl Dynamic {
+    /// Registers `T` so a `Dynamic` holding it can be decoded. Both ends of a connection need to register it.
+    pub fn register<T>() where T: ReflectMessage + Name + Default {
+        MESSAGE_DECODERS.write().unwrap().insert(<T as Name>::type_url(), decode_message::<T>);
+    }
+
+    fn decode_value(any: &Any) -> Result<Box<dyn ReflectMessage>, DecodeError> {
+        let decode = MESSAGE_DECODERS.read().unwrap().get(&any.type_url).copied()
+            .ok_or_else(|| DecodeError::new(format!("no message registered for type URL {}", any.type_url)))?;
+        decode(&any.value)
+    }
+
     pub fn new<T>(value: T) -> Dynamic where T: ReflectMessage {
         Dynamic {
+            pending: Any::default(),
             value: Box::new(value)
             /* ,
             cloned_func: Arc::new(|value| {
@@ -243,6 +310,7 @@ impl Dynamic {
     }
     pub fn from_reflect(value: Box<dyn ReflectMessage>) -> Dynamic {
         Dynamic {
+            pending: Any::default(),
             value: value
             /*
             cloned_func: Arc::new(|value: &Box<dyn Reflect>| {
@@ -254,9 +322,6 @@ impl Dynamic {
         }
     }
     pub fn cast<T>(self) -> Option<T> where T: ReflectMessage + FromReflect + Typed {
-        dbg!(self.value.reflect_type_path());
-        dbg!(T::type_info().type_path());
-
         if self.value.reflect_type_path() == T::type_info().type_path() {
             T::from_reflect(self.value.as_reflect())
         } else {
@@ -273,4 +338,32 @@ impl Clone for Dynamic {
             //cloned_func: self.cloned_func.clone()
         }
     }
-} */
\ No newline at end of file
+} */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Reflect, Clone, PartialEq, Message)]
+    struct Greeting {
+        #[prost(string, tag = "1")]
+        text: String,
+    }
+
+    impl Name for Greeting {
+        const NAME: &'static str = "Greeting";
+        const PACKAGE: &'static str = "flux.test";
+    }
+
+    /// Encodes and decodes a `Dynamic` the way tonic does for the `Dynamic` returned by `FluxService::Test`.
+    #[test]
+    fn registered_message_round_trips() {
+        Dynamic::register::<Greeting>();
+        let greeting = Greeting { text: "hello".to_string() };
+
+        let bytes = Dynamic::new(greeting.clone()).encode_to_vec();
+        let decoded = Dynamic::decode(bytes.as_slice()).unwrap();
+
+        assert_eq!(decoded.cast::<Greeting>(), Some(greeting));
+    }
+}
@@ -7,7 +7,7 @@ use bevy::{ecs::system::SystemId, prelude::*};
 use serde::{Serialize, Deserialize};
 use std::clone::Clone;
 
-#[derive(Debug, Clone, Default, Component)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Component, Reflect, Reactive)]
 pub struct Slider {
     pub fill_entity: Option<Entity>,
     pub percent: f32
@@ -26,6 +26,9 @@ pub mod builder;
 #[cfg(feature = "bevy_std")]
 pub mod elements;
 
+#[cfg(feature = "prost")]
+pub mod dynamic;
+
 pub mod constants;
 pub mod functions;
 pub mod plugin;
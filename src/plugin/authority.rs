@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Which peer may change a replicated entity. Changes from any other peer are rejected.
+/// Replicated entities without one are treated as `Authority::Server`.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Authority {
+    /// Only the peer that replicates the entity.
+    #[default]
+    Server,
+    /// The given peer, which sends its changes to the server to be replicated to everyone else.
+    Peer(Id),
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct AuthorityConfig {
+    /// Grants requests for entities nobody else has authority over right away,
+    /// instead of leaving them to `AuthorityRequested` handlers.
+    pub grant_unclaimed: bool,
+}
+
+/// Asks the server for authority over a replicated entity.
+#[derive(Reflect, Clone, Debug)]
+pub struct RequestAuthority {
+    pub entity_id: Id,
+}
+
+/// Gives authority over a replicated entity back to the server.
+#[derive(Reflect, Clone, Debug)]
+pub struct ReleaseAuthority {
+    pub entity_id: Id,
+}
+
+/// Hands authority over a replicated entity to another peer. Only the current authority may send it.
+#[derive(Reflect, Clone, Debug)]
+pub struct TransferAuthority {
+    pub entity_id: Id,
+    pub peer_id: Id,
+}
+
+/// Tells peers who has authority over a replicated entity.
+#[derive(Reflect, Clone, Debug)]
+pub struct AuthorityChanged {
+    pub entity_id: Id,
+    pub authority: Authority,
+}
+
+/// Sent on the server when a peer asks for authority over one of its replicated entities.
+/// Grant it with `grant_authority`.
+#[derive(Event, Debug, Clone)]
+pub struct AuthorityRequested {
+    pub peer_id: Id,
+    pub entity: Entity,
+}
+
+pub trait AuthorityCommandsExt {
+    /// Gives `peer_id` authority over this replicated entity, taking it from whoever had it.
+    fn grant_authority(&mut self, peer_id: Id) -> &mut Self;
+
+    /// Gives authority over this replicated entity back to the server.
+    fn revoke_authority(&mut self) -> &mut Self;
+
+    /// Asks the peer this mirror is replicated from for authority over it.
+    fn request_authority(&mut self) -> &mut Self;
+
+    /// Gives up authority over this mirror.
+    fn release_authority(&mut self) -> &mut Self;
+
+    /// Hands authority over this mirror, which this peer must have, to `peer_id`.
+    fn transfer_authority(&mut self, peer_id: Id) -> &mut Self;
+}
+
+impl AuthorityCommandsExt for EntityCommands<'_> {
+    fn grant_authority(&mut self, peer_id: Id) -> &mut Self {
+        self.insert(Authority::Peer(peer_id))
+    }
+
+    fn revoke_authority(&mut self) -> &mut Self {
+        self.insert(Authority::Server)
+    }
+
+    fn request_authority(&mut self) -> &mut Self {
+        self.queue(|entity: EntityWorldMut| send_to_owner(&entity, |entity_id| RequestAuthority { entity_id }))
+    }
+
+    fn release_authority(&mut self) -> &mut Self {
+        self.queue(|entity: EntityWorldMut| send_to_owner(&entity, |entity_id| ReleaseAuthority { entity_id }))
+    }
+
+    fn transfer_authority(&mut self, peer_id: Id) -> &mut Self {
+        self.queue(move |entity: EntityWorldMut| send_to_owner(&entity, |entity_id| TransferAuthority { entity_id, peer_id }))
+    }
+}
+
+fn send_to_owner<T: NetworkPayload>(entity: &EntityWorldMut, ev: impl FnOnce(Id) -> T) {
+    let Some(session) = entity.world().get_resource::<Session>() else {
+        return;
+    };
+    match entity.get::<NetworkId>() {
+        Some(NetworkId { id, owner: Some(owner_id) }) => {
+            session.get_multiplexer().send(*owner_id, NetworkEvent::new(session.get_id(), ev(*id)));
+        },
+        _ => warn!("Can't send {} for {}, it isn't a mirror of another peer's entity", T::short_type_path(), entity.id()),
+    }
+}
+
+/// Handles authority requests, releases and transfers for local replicated entities.
+pub fn receive_authority_requests(
+    mut commands: Commands,
+    session: Res<Session>,
+    config: Res<AuthorityConfig>,
+    interest: Res<PeerInterest>,
+    entity_map: Res<NetworkEntityMap>,
+    authorities: Query<Option<&Authority>, With<Replicated>>,
+    mut request_evs: EventReader<Received<RequestAuthority>>,
+    mut release_evs: EventReader<Received<ReleaseAuthority>>,
+    mut transfer_evs: EventReader<Received<TransferAuthority>>,
+    mut requested_evs: EventWriter<AuthorityRequested>,
+    mut rejected_evs: EventWriter<EventRejected>,
+) {
+    let get_authority = |entity_id: &Id| {
+        let entity = entity_map.get_owner_map(None)?.get_entity(entity_id)?;
+        let authority = authorities.get(entity).ok()?;
+        Some((entity, authority.copied().unwrap_or_default()))
+    };
+    let mut reject = |sender_id: Id, type_path: &str| {
+        rejected_evs.send(EventRejected {
+            sender_id,
+            recipient_id: session.get_id(),
+            type_path: Some(type_path.to_string()),
+            reason: RejectReason::NoAuthority,
+        });
+    };
+
+    for Received { sender_id, ev } in request_evs.read() {
+        // Peers can only ask for entities they can see
+        let Some((entity, authority)) = get_authority(&ev.entity_id).filter(|(entity, _)| interest.is_visible(*sender_id, *entity)) else {
+            continue;
+        };
+        if authority == Authority::Peer(*sender_id) {
+            continue;
+        }
+        if config.grant_unclaimed && authority == Authority::Server {
+            commands.entity(entity).grant_authority(*sender_id);
+        } else {
+            requested_evs.send(AuthorityRequested {
+                peer_id: *sender_id,
+                entity,
+            });
+        }
+    }
+
+    for Received { sender_id, ev } in release_evs.read() {
+        match get_authority(&ev.entity_id) {
+            Some((entity, authority)) if authority == Authority::Peer(*sender_id) => {
+                commands.entity(entity).revoke_authority();
+            },
+            _ => reject(*sender_id, ReleaseAuthority::type_path()),
+        }
+    }
+
+    for Received { sender_id, ev } in transfer_evs.read() {
+        match get_authority(&ev.entity_id) {
+            Some((entity, authority)) if authority == Authority::Peer(*sender_id) && interest.is_visible(ev.peer_id, entity) => {
+                commands.entity(entity).grant_authority(ev.peer_id);
+            },
+            _ => reject(*sender_id, TransferAuthority::type_path()),
+        }
+    }
+}
+
+/// Tells peers about authority changes on the replicated entities they can see, and about the
+/// authority over entities that just entered their interest.
+pub fn send_authority_changes(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    interest: Res<PeerInterest>,
+    changed: Query<(Entity, &NetworkId, &Authority), (With<Replicated>, Changed<Authority>)>,
+    authorities: Query<(&NetworkId, &Authority), With<Replicated>>,
+) {
+    let multiplexer = session.get_multiplexer();
+    let send = |peer_id: Id, network_id: &NetworkId, authority: &Authority| {
+        multiplexer.send(peer_id, NetworkEvent::new(session.get_id(), AuthorityChanged {
+            entity_id: network_id.id,
+            authority: *authority,
+        }));
+    };
+
+    for (entity, network_id, authority) in changed.iter() {
+        for peer_id in interest.get_peers(entity) {
+            send(peer_id, network_id, authority);
+        }
+    }
+
+    for peer_id in multiplexer.get_subscribers(&config.topic) {
+        for entity in interest.get_entered(peer_id) {
+            // Already sent above
+            if changed.contains(entity) {
+                continue;
+            }
+            if let Ok((network_id, authority)) = authorities.get(entity) {
+                send(peer_id, network_id, authority);
+            }
+        }
+    }
+}
+
+/// Applies authority changes from the peers mirrors are replicated from.
+pub fn apply_authority_changes(
+    mut commands: Commands,
+    entity_map: Res<NetworkEntityMap>,
+    mut changed_evs: EventReader<Received<AuthorityChanged>>,
+) {
+    for Received { sender_id, ev } in changed_evs.read() {
+        let mirror = entity_map.get_owner_map(Some(*sender_id)).and_then(|map| map.get_entity(&ev.entity_id));
+        if let Some(entity) = mirror {
+            commands.entity(entity).insert(ev.authority);
+        }
+    }
+}
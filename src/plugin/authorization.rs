@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Decides whether a sender may send an event to a recipient. Called with the sender, recipient and event.
+pub type AuthorizeEvent = Arc<dyn Fn(Id, Id, &NetworkEvent) -> bool + Send + Sync>;
+
+/// Per-payload-type rules for events arriving from remote peers. Events sent locally are trusted.
+/// Cloning shares the rules, so rules added after the policy is applied still take effect.
+#[derive(Resource, Clone, Default)]
+pub struct AuthorizationPolicy {
+    rules: Arc<RwLock<HashMap<String, AuthorizeEvent>>>,
+    /// Rejects payload types without a rule, instead of allowing them.
+    deny_unlisted: bool,
+}
+
+impl AuthorizationPolicy {
+    /// A policy that only allows payload types with a rule.
+    pub fn deny_by_default() -> Self {
+        Self {
+            deny_unlisted: true,
+            ..default()
+        }
+    }
+
+    /// Sets the rule for events carrying a `T`, replacing any previous one.
+    pub fn allow<T, F>(&self, rule: F) -> &Self where T: TypePath, F: Fn(Id, Id, &NetworkEvent) -> bool + Send + Sync + 'static {
+        self.rules.write().unwrap().insert(T::type_path().to_string(), Arc::new(rule));
+        self
+    }
+
+    /// Allows any peer to send a `T` to anyone.
+    pub fn allow_all<T>(&self) -> &Self where T: TypePath {
+        self.allow::<T, _>(|_, _, _| true)
+    }
+
+    /// Rejects every `T` from remote peers.
+    pub fn deny<T>(&self) -> &Self where T: TypePath {
+        self.allow::<T, _>(|_, _, _| false)
+    }
+
+    pub fn is_authorized(&self, sender_id: Id, recipient_id: Id, ev: &NetworkEvent) -> bool {
+        let rules = self.rules.read().unwrap();
+        match ev.type_path().and_then(|type_path| rules.get(type_path)) {
+            Some(rule) => rule(sender_id, recipient_id, ev),
+            None => !self.deny_unlisted,
+        }
+    }
+}
+
+/// Why an incoming event was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    Unauthorized,
+    /// The sender exceeded a `RateLimitConfig` limit.
+    RateLimited,
+    /// The sender tried to change a replicated entity it doesn't have `Authority` over, or to create one
+    /// without being one of `ReplicationConfig::sources`.
+    NoAuthority,
+}
+
+/// Audit event raised for each incoming event that was dropped.
+#[derive(Event, Debug, Clone)]
+pub struct EventRejected {
+    pub sender_id: Id,
+    pub recipient_id: Id,
+    /// Type path of the payload, if it represents a known type.
+    pub type_path: Option<String>,
+    pub reason: RejectReason,
+}
+
+/// Applies the `AuthorizationPolicy` resource, if the app inserted one, to the session's multiplexer.
+pub fn apply_authorization_policy(session: Res<Session>, policy: Option<Res<AuthorizationPolicy>>) {
+    if let Some(policy) = policy.filter(|policy| policy.is_changed()) {
+        session.get_multiplexer().set_authorization_policy(Some(policy.clone()));
+    }
+}
+
+pub fn relay_rejected_events(session: Res<Session>, mut rejected_evs: EventWriter<EventRejected>) {
+    for ev in session.get_multiplexer().drain_rejected_evs() {
+        rejected_evs.send(ev);
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy_wasm_tasks::*;
+use serde::{Serialize, Deserialize};
+
+/// Table holding mail for offline peers, so it survives restarts.
+const MAILBOX_TABLE: &str = "mailbox";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredMail {
+    peer_id: Id,
+    /// The event, encoded as JSON.
+    ev: String,
+    /// Time left before the mail expires, or `None` if it's held until delivered.
+    expiry: Option<surrealdb::sql::Duration>,
+}
+
+/// Loads mail persisted by a previous run into the session's multiplexer.
+pub fn load_mailboxes(session: Res<Session>, db_config: Res<DBConfig>, tasks: Tasks) {
+    let multiplexer = session.get_multiplexer();
+    let db = db_config.db.clone();
+
+    tasks.spawn_auto(async move |_| {
+        let db = db.lock().await;
+        let result = db.query("DELETE type::table($table) WHERE expires_at != NONE AND expires_at <= time::now()")
+            .query("SELECT peer_id, ev, stored_at, expires_at - time::now() AS expiry FROM type::table($table) ORDER BY stored_at")
+            .bind(("table", MAILBOX_TABLE))
+            .await
+            .and_then(|mut response| response.take::<Vec<StoredMail>>(1));
+
+        match result {
+            Ok(mail) => {
+                for mail in mail {
+                    match serde_json::from_str::<NetworkEvent>(&mail.ev) {
+                        Ok(ev) => multiplexer.restore_mail(mail.peer_id, ev, mail.expiry.map(|expiry| expiry.0)),
+                        Err(err) => warn!("Skipping stored mail for peer {} that failed to decode: {}", mail.peer_id, err),
+                    }
+                }
+            },
+            Err(err) => warn!("Failed to load stored mail: {}", err),
+        }
+    });
+}
+
+/// Mirrors mailbox changes on the session's multiplexer into the database.
+pub fn persist_mailboxes(session: Res<Session>, db_config: Res<DBConfig>, tasks: Tasks) {
+    let mailbox_evs = session.get_multiplexer().drain_mailbox_evs();
+    if mailbox_evs.is_empty() {
+        return;
+    }
+
+    let db = db_config.db.clone();
+    tasks.spawn_auto(async move |_| {
+        let db = db.lock().await;
+        for mailbox_ev in mailbox_evs {
+            let result = match mailbox_ev {
+                MailboxEvent::Stored { peer_id, ev, expiry } => {
+                    let ev = match serde_json::to_string(&ev) {
+                        Ok(ev) => ev,
+                        Err(err) => {
+                            warn!("Failed to encode mail for peer {}: {}", peer_id, err);
+                            continue;
+                        }
+                    };
+                    db.query("CREATE type::table($table) CONTENT { peer_id: $peer_id, ev: $ev, stored_at: time::now(), expires_at: IF $expiry != NONE THEN time::now() + $expiry ELSE NONE END }")
+                        .bind(("table", MAILBOX_TABLE))
+                        .bind(("peer_id", peer_id))
+                        .bind(("ev", ev))
+                        .bind(("expiry", expiry.map(surrealdb::sql::Duration::from)))
+                        .await
+                },
+                MailboxEvent::Emptied { peer_id } => {
+                    db.query("DELETE type::table($table) WHERE peer_id = $peer_id")
+                        .bind(("table", MAILBOX_TABLE))
+                        .bind(("peer_id", peer_id))
+                        .await
+                },
+            };
+
+            if let Err(err) = result {
+                warn!("Failed to persist mailbox change: {}", err);
+            }
+        }
+    });
+}
@@ -11,4 +11,9 @@ pub use extensions::*;
 #[cfg(feature = "surrealdb")]
 mod surrealdb;
 #[cfg(feature = "surrealdb")]
-pub use surrealdb::*;
\ No newline at end of file
+pub use surrealdb::*;
+
+#[cfg(feature = "surrealdb")]
+mod mailbox;
+#[cfg(feature = "surrealdb")]
+pub use mailbox::*;
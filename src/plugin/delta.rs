@@ -0,0 +1,155 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+use bevy::reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy::reflect::{ReflectFromReflect, ReflectRef, TypeRegistry};
+use serde::de::DeserializeSeed;
+use crate::prelude::*;
+
+/// A changed field, by reflect path, with its new value encoded with postcard.
+#[derive(Reflect, Clone, Debug)]
+pub struct FieldPatch {
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+/// Paths of the fields that differ between `old` and `new`, which should be values of the same type.
+/// Nested structs, tuples, enums with an unchanged variant and lists with an unchanged length are
+/// compared field by field; anything else that differs is reported as a whole.
+pub fn diff_fields(old: &dyn PartialReflect, new: &dyn PartialReflect) -> Vec<OptionalParsedPath> {
+    let mut changes = Vec::new();
+    diff_into(old, new, &mut Vec::new(), &mut changes);
+    changes
+}
+
+fn diff_into(old: &dyn PartialReflect, new: &dyn PartialReflect, path: &mut Vec<Access<'static>>, changes: &mut Vec<OptionalParsedPath>) {
+    let mut diff_field = |old: &dyn PartialReflect, new: &dyn PartialReflect, access: Access<'static>, changes: &mut Vec<OptionalParsedPath>| {
+        path.push(access);
+        diff_into(old, new, path, changes);
+        path.pop();
+    };
+
+    match (old.reflect_ref(), new.reflect_ref()) {
+        (ReflectRef::Struct(old), ReflectRef::Struct(new)) if old.field_len() == new.field_len() => {
+            for i in 0..new.field_len() {
+                let name = new.name_at(i).unwrap().to_string();
+                diff_field(old.field_at(i).unwrap(), new.field_at(i).unwrap(), Access::Field(Cow::Owned(name)), changes);
+            }
+        },
+        (ReflectRef::TupleStruct(old), ReflectRef::TupleStruct(new)) if old.field_len() == new.field_len() => {
+            for i in 0..new.field_len() {
+                diff_field(old.field(i).unwrap(), new.field(i).unwrap(), Access::TupleIndex(i), changes);
+            }
+        },
+        (ReflectRef::Tuple(old), ReflectRef::Tuple(new)) if old.field_len() == new.field_len() => {
+            for i in 0..new.field_len() {
+                diff_field(old.field(i).unwrap(), new.field(i).unwrap(), Access::TupleIndex(i), changes);
+            }
+        },
+        (ReflectRef::List(old), ReflectRef::List(new)) if old.len() == new.len() => {
+            for i in 0..new.len() {
+                diff_field(old.get(i).unwrap(), new.get(i).unwrap(), Access::ListIndex(i), changes);
+            }
+        },
+        (ReflectRef::Array(old), ReflectRef::Array(new)) if old.len() == new.len() => {
+            for i in 0..new.len() {
+                diff_field(old.get(i).unwrap(), new.get(i).unwrap(), Access::ListIndex(i), changes);
+            }
+        },
+        (ReflectRef::Enum(old), ReflectRef::Enum(new)) if old.variant_name() == new.variant_name() && old.field_len() == new.field_len() => {
+            for i in 0..new.field_len() {
+                let access = match new.name_at(i) {
+                    Some(name) => Access::Field(Cow::Owned(name.to_string())),
+                    None => Access::TupleIndex(i),
+                };
+                diff_field(old.field_at(i).unwrap(), new.field_at(i).unwrap(), access, changes);
+            }
+        },
+        _ => {
+            if !old.reflect_partial_eq(new).unwrap_or(false) {
+                changes.push(path.clone().into());
+            }
+        },
+    }
+}
+
+/// Encodes the values at `paths` in `value`.
+pub fn encode_patch(value: &dyn PartialReflect, paths: Vec<OptionalParsedPath>, registry: &TypeRegistry) -> Result<Vec<FieldPatch>> {
+    paths.into_iter().map(|path| {
+        let path_string = path.to_string();
+        let field = path.reflect_element(value).map_err(|err| anyhow!("Failed to read {}: {}", path_string, err))?;
+        Ok(FieldPatch {
+            data: postcard::to_allocvec(&TypedReflectSerializer::new(field, registry))?,
+            path: path_string,
+        })
+    }).collect()
+}
+
+/// Sets the fields in `patch` on `value`. Fields are replaced as a whole, so lists can shrink as well as grow.
+pub fn apply_patch(value: &mut dyn PartialReflect, patch: &[FieldPatch], registry: &TypeRegistry) -> Result<()> {
+    for field_patch in patch {
+        let path = OptionalParsedPath::parse(&field_patch.path).map_err(|err| anyhow!("Invalid patch path {}: {}", field_patch.path, err))?;
+        let field = path.reflect_element_mut(value).map_err(|err| anyhow!("Failed to write {}: {}", field_patch.path, err))?;
+
+        let type_info = field.get_represented_type_info()
+            .ok_or_else(|| anyhow!("Field {} has no type info", field_patch.path))?;
+        let registration = registry.get(type_info.type_id())
+            .ok_or_else(|| anyhow!("Type {} isn't registered", type_info.type_path()))?;
+
+        let mut deserializer = postcard::Deserializer::from_bytes(&field_patch.data);
+        let patch_value = TypedReflectDeserializer::new(registration, registry).deserialize(&mut deserializer)?;
+
+        let concrete_value = registration.data::<ReflectFromReflect>()
+            .and_then(|from_reflect| from_reflect.from_reflect(patch_value.as_partial_reflect()));
+        if let Some(concrete_value) = concrete_value && let Some(field) = field.try_as_reflect_mut() {
+            field.set(concrete_value).map_err(|_| anyhow!("Failed to set {}", field_patch.path))?;
+        } else {
+            field.try_apply(patch_value.as_partial_reflect())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Reflect, Clone, Debug, PartialEq)]
+    struct Inner {
+        x: f32,
+        tags: Vec<String>,
+    }
+
+    #[derive(Reflect, Clone, Debug, PartialEq)]
+    struct State {
+        name: String,
+        inner: Inner,
+        count: u32,
+    }
+
+    #[test]
+    fn patch_of_a_diff_turns_old_into_new() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<State>();
+
+        let old = State {
+            name: "a".to_string(),
+            inner: Inner { x: 1.0, tags: vec!["a".to_string()] },
+            count: 1,
+        };
+        let new = State {
+            inner: Inner { x: 2.0, tags: vec!["a".to_string(), "b".to_string()] },
+            count: 2,
+            ..old.clone()
+        };
+
+        let paths = diff_fields(&old, &new);
+        assert_eq!(paths.len(), 3);
+
+        let patch = encode_patch(&new, paths, &registry).unwrap();
+        let mut value = old.clone();
+        apply_patch(&mut value, &patch, &registry).unwrap();
+        assert_eq!(value, new);
+    }
+}
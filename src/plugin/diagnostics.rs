@@ -0,0 +1,54 @@
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Publishes aggregate multiplexer metrics as Bevy diagnostics, e.g. for `LogDiagnosticsPlugin`.
+#[derive(Default)]
+pub struct MultiplexerDiagnosticsPlugin;
+
+impl MultiplexerDiagnosticsPlugin {
+    pub const EVENTS_SENT: DiagnosticPath = DiagnosticPath::const_new("flux/multiplexer/events_sent_per_second");
+    pub const EVENTS_RECEIVED: DiagnosticPath = DiagnosticPath::const_new("flux/multiplexer/events_received_per_second");
+    pub const CHANNELS: DiagnosticPath = DiagnosticPath::const_new("flux/multiplexer/channels");
+    pub const BUFFERED_EVENTS: DiagnosticPath = DiagnosticPath::const_new("flux/multiplexer/buffered_events");
+    /// Age of the oldest unread event across all channels. Keeps growing while a channel is stuck.
+    pub const OLDEST_PENDING_AGE: DiagnosticPath = DiagnosticPath::const_new("flux/multiplexer/oldest_pending_age");
+}
+
+impl Plugin for MultiplexerDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_diagnostic(Diagnostic::new(Self::EVENTS_SENT))
+            .register_diagnostic(Diagnostic::new(Self::EVENTS_RECEIVED))
+            .register_diagnostic(Diagnostic::new(Self::CHANNELS))
+            .register_diagnostic(Diagnostic::new(Self::BUFFERED_EVENTS))
+            .register_diagnostic(Diagnostic::new(Self::OLDEST_PENDING_AGE).with_suffix("s"))
+            .add_systems(Update, measure_multiplexer.run_if(resource_exists::<Session>));
+    }
+}
+
+fn measure_multiplexer(
+    session: Res<Session>,
+    time: Res<Time<Real>>,
+    mut last_totals: Local<Option<MultiplexerTotals>>,
+    mut diagnostics: Diagnostics,
+) {
+    let multiplexer = session.get_multiplexer();
+
+    let totals = multiplexer.get_totals();
+    let delta = time.delta_secs_f64();
+    if let Some(last_totals) = *last_totals && delta > 0.0 {
+        diagnostics.add_measurement(&MultiplexerDiagnosticsPlugin::EVENTS_SENT, || (totals.sent - last_totals.sent) as f64 / delta);
+        diagnostics.add_measurement(&MultiplexerDiagnosticsPlugin::EVENTS_RECEIVED, || (totals.received - last_totals.received) as f64 / delta);
+    }
+    *last_totals = Some(totals);
+
+    let snapshot = multiplexer.snapshot();
+    diagnostics.add_measurement(&MultiplexerDiagnosticsPlugin::CHANNELS, || snapshot.len() as f64);
+    diagnostics.add_measurement(&MultiplexerDiagnosticsPlugin::BUFFERED_EVENTS, || {
+        snapshot.iter().map(|channel| channel.buffer_depth).sum::<usize>() as f64
+    });
+    diagnostics.add_measurement(&MultiplexerDiagnosticsPlugin::OLDEST_PENDING_AGE, || {
+        snapshot.iter().filter_map(|channel| channel.oldest_pending_age).max().unwrap_or_default().as_secs_f64()
+    });
+}
@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Only `peer_id` receives this replicated entity.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct OwnerOnly {
+    pub peer_id: Id,
+}
+
+/// Only subscribers of `topic` receive this replicated entity, so topics double as rooms.
+#[derive(Component, Debug, Clone)]
+pub struct InRoom {
+    pub topic: String,
+}
+
+impl InRoom {
+    pub fn new(topic: impl Into<String>) -> Self {
+        Self {
+            topic: topic.into(),
+        }
+    }
+}
+
+/// Only peers with a `Viewpoint` within `radius` of this replicated entity receive it.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct InterestRadius {
+    pub radius: f32,
+}
+
+/// Where `peer_id` sees the world from for `InterestRadius`, usually the peer's player entity.
+/// A peer may have several viewpoints.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Viewpoint {
+    pub peer_id: Id,
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InterestSet {
+    /// Works out which replicated entities each subscribed peer receives from the rule components.
+    Evaluate,
+    /// Custom predicate systems go here, and can narrow what each peer receives with `PeerInterest::hide`.
+    Filter,
+}
+
+/// Replicated entities each peer receives this frame. An entity is only sent to a peer if it has a rule
+/// and every rule on it allows that peer. Entities without rules aren't sent to anyone, unless
+/// `ReplicationConfig::open_visibility` is on, in which case they go to every subscriber of the replication topic.
+#[derive(Resource, Default)]
+pub struct PeerInterest {
+    visible: HashMap<Id, HashSet<Entity>>,
+    previous: HashMap<Id, HashSet<Entity>>,
+}
+
+impl PeerInterest {
+    pub fn is_visible(&self, peer_id: Id, entity: Entity) -> bool {
+        self.visible.get(&peer_id).is_some_and(|entities| entities.contains(&entity))
+    }
+
+    /// Peers that receive `entity`.
+    pub fn get_peers(&self, entity: Entity) -> Vec<Id> {
+        self.visible.iter()
+            .filter(|(_, entities)| entities.contains(&entity))
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    pub fn get_visible(&self, peer_id: Id) -> impl Iterator<Item = Entity> + '_ {
+        self.visible.get(&peer_id).into_iter().flatten().copied()
+    }
+
+    /// Stops sending `entity` to `peer_id` this frame.
+    pub fn hide(&mut self, peer_id: Id, entity: Entity) {
+        if let Some(entities) = self.visible.get_mut(&peer_id) {
+            entities.remove(&entity);
+        }
+    }
+
+    /// Keeps only the entities `peer_id` receives for which `f` returns true.
+    pub fn retain(&mut self, peer_id: Id, f: impl FnMut(&Entity) -> bool) {
+        if let Some(entities) = self.visible.get_mut(&peer_id) {
+            entities.retain(f);
+        }
+    }
+
+    /// Entities `peer_id` started receiving this frame.
+    pub fn get_entered(&self, peer_id: Id) -> Vec<Entity> {
+        let previous = self.previous.get(&peer_id);
+        self.get_visible(peer_id)
+            .filter(|entity| !previous.is_some_and(|previous| previous.contains(entity)))
+            .collect()
+    }
+
+    /// Whether `peer_id` started receiving `entity` this frame.
+    pub fn has_entered(&self, peer_id: Id, entity: Entity) -> bool {
+        self.is_visible(peer_id, entity) && !self.previous.get(&peer_id).is_some_and(|previous| previous.contains(&entity))
+    }
+
+    /// Every peer with the entities it stopped receiving this frame, including despawned ones.
+    pub fn get_left(&self) -> Vec<(Id, Vec<Entity>)> {
+        self.previous.iter()
+            .map(|(peer_id, previous)| {
+                let left = previous.iter()
+                    .filter(|entity| !self.is_visible(*peer_id, **entity))
+                    .copied()
+                    .collect();
+                (*peer_id, left)
+            })
+            .collect()
+    }
+}
+
+/// Starts a new frame of `PeerInterest` from the `OwnerOnly`, `InRoom` and `InterestRadius` rules.
+pub fn evaluate_interest(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    mut interest: ResMut<PeerInterest>,
+    entities: Query<(Entity, Option<&OwnerOnly>, Option<&InRoom>, Option<&InterestRadius>, Option<&GlobalTransform>), With<Replicated>>,
+    viewpoints: Query<(&Viewpoint, &GlobalTransform)>,
+) {
+    let multiplexer = session.get_multiplexer();
+    let interest = &mut *interest;
+    interest.previous = std::mem::take(&mut interest.visible);
+
+    let mut rooms: HashMap<&str, HashSet<Id>> = HashMap::new();
+    for peer_id in multiplexer.get_subscribers(&config.topic) {
+        if peer_id == session.get_id() {
+            continue;
+        }
+        let positions: Vec<Vec3> = viewpoints.iter()
+            .filter(|(viewpoint, _)| viewpoint.peer_id == peer_id)
+            .map(|(_, transform)| transform.translation())
+            .collect();
+
+        let visible = interest.visible.entry(peer_id).or_default();
+        for (entity, owner_only, in_room, radius, transform) in entities.iter() {
+            let has_rules = owner_only.is_some() || in_room.is_some() || radius.is_some();
+            if !has_rules && !config.open_visibility {
+                continue;
+            }
+            if owner_only.is_some_and(|owner_only| owner_only.peer_id != peer_id) {
+                continue;
+            }
+            if let Some(in_room) = in_room {
+                let members = rooms.entry(&in_room.topic).or_insert_with(|| multiplexer.get_subscribers(&in_room.topic).into_iter().collect());
+                if !members.contains(&peer_id) {
+                    continue;
+                }
+            }
+            if let Some(radius) = radius {
+                // Entities without a position are out of range of everyone
+                let in_range = transform.is_some_and(|transform| {
+                    positions.iter().any(|position| position.distance(transform.translation()) <= radius.radius)
+                });
+                if !in_range {
+                    continue;
+                }
+            }
+            visible.insert(entity);
+        }
+    }
+}
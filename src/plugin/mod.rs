@@ -12,6 +12,50 @@ pub use multiplexer::*;
 mod systems;
 pub use systems::*;
 
+mod network_events;
+pub use network_events::*;
+
+mod rpc;
+pub use rpc::*;
+
+mod presence;
+pub use presence::*;
+
+mod topics;
+pub use topics::*;
+
+mod authorization;
+pub use authorization::*;
+
+mod rate_limit;
+pub use rate_limit::*;
+
+mod diagnostics;
+pub use diagnostics::*;
+
+mod recording;
+pub use recording::*;
+
+mod delta;
+pub use delta::*;
+
+mod network_id;
+pub use network_id::*;
+
+mod replication;
+pub use replication::*;
+
+mod interest;
+pub use interest::*;
+
+mod authority;
+pub use authority::*;
+
+#[cfg(feature = "futures")]
+mod transport;
+#[cfg(feature = "futures")]
+pub use transport::*;
+
 use bevy::{prelude::*, reflect::DynamicStruct};
 use crate::prelude::*;
 
@@ -27,7 +71,15 @@ pub struct Session {
 
 impl Session {
     pub fn new(peer_id: Id) -> Self {
-        let multiplexer = Multiplexer::new();
+        Self::with_config(peer_id, ChannelConfig::default())
+    }
+
+    /// Creates a session whose multiplexer channels use the given capacity and overflow policy.
+    /// The session's own channel stays unbounded, since it's drained every frame by `relay_network_events`
+    /// and a `Disconnect` policy would otherwise cut the session off for good.
+    pub fn with_config(peer_id: Id, channel_config: ChannelConfig) -> Self {
+        let multiplexer = Multiplexer::with_config(channel_config);
+        multiplexer.configure_channel(peer_id, ChannelConfig::default());
         Self {
             multiplexer: multiplexer.clone(),
             channel: multiplexer.get_channel(peer_id),
@@ -60,6 +112,13 @@ impl Session {
     }
 }
 
+/// System sets used by Flux so app systems can be ordered around networking.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NetworkSet {
+    /// Sends outgoing `PeerEvent`s and forwards received `NetworkEvent`s.
+    Relay,
+}
+
 pub struct FluxPlugin {
     config: FluxConfig,
 }
@@ -78,9 +137,45 @@ impl Plugin for FluxPlugin {
             .insert_state(DbState::Connecting)
             .insert_resource(self.config.clone())
             .insert_resource(BindingsConfig::default())
+            .init_resource::<NetworkRelayConfig>()
             .add_event::<NetworkEvent>()
             .add_event::<PeerEvent>()
-            .add_systems(Update, (relay_network_events).run_if(in_state(DbState::Connected)));
+            .add_event::<ChannelOverflowEvent>()
+            .init_resource::<PresenceConfig>()
+            .init_resource::<Presence>()
+            .add_event::<PeerConnected>()
+            .add_event::<PeerDisconnected>()
+            .add_event::<PeerIdle>()
+            .add_event::<TopicJoined>()
+            .add_event::<TopicLeft>()
+            .add_event::<EventRejected>()
+            .init_resource::<NetworkEntityMap>()
+            .add_network_event::<SubscribeTopic>()
+            .add_network_event::<UnsubscribeTopic>()
+            .add_network_event::<PublishTopic>()
+            .configure_sets(Update, NetworkSet::Relay.run_if(in_state(DbState::Connected)))
+            .add_systems(Update, (
+                relay_network_events,
+                relay_overflow_events,
+                expire_parked_channels,
+                apply_mailbox_config,
+                update_presence,
+                relay_topic_changes,
+                apply_authorization_policy,
+                relay_rejected_events,
+                apply_rate_limits,
+            ).in_set(NetworkSet::Relay))
+            .add_systems(Update, (
+                (replay_network_events.run_if(resource_exists::<NetworkReplay>), apply_recorder_tap)
+                    .before(NetworkSet::Relay),
+                (record_outgoing_events, record_incoming_events).chain()
+                    .run_if(resource_exists::<NetworkRecorder>)
+                    .after(NetworkSet::Relay),
+            ).run_if(in_state(DbState::Connected)))
+            .add_systems(PostUpdate, (
+                index_network_ids.before(ReplicationSet::Send),
+                unindex_network_ids.after(ReplicationSet::Send),
+            ));
         
         #[cfg(feature = "bevy_std")]
         app
@@ -88,7 +183,9 @@ impl Plugin for FluxPlugin {
 
         #[cfg(feature = "surrealdb")]
         app
-            .add_systems(PreStartup, (startup, database::start).chain());
+            .add_systems(PreStartup, (startup, database::start).chain())
+            .add_systems(OnEnter(DbState::Connected), load_mailboxes)
+            .add_systems(Update, persist_mailboxes.after(NetworkSet::Relay).run_if(in_state(DbState::Connected)));
 
         #[cfg(feature = "futures")]
         app
@@ -110,7 +207,9 @@ fn startup(world: &mut World) {
 
 pub fn relay_network_events(
     mut session: ResMut<Session>,
+    config: Res<NetworkRelayConfig>,
     mut peer_evs: ResMut<Events<PeerEvent>>, mut network_evs: ResMut<Events<NetworkEvent>>,
+    mut recorder: Option<ResMut<NetworkRecorder>>,
 ) {
     for ev in peer_evs.get_cursor().read(&peer_evs) {
         //info!("Sending network event of type {:?}!", ev.network_event.as_ref().unwrap().network_event_type.clone().unwrap());
@@ -119,8 +218,40 @@ pub fn relay_network_events(
     peer_evs.clear();
 
     //info!("Trying to receive network events...");
-    if let Some(ev) = session.get_channel_mut().try_recv() {
+    let mut relayed = 0;
+    while config.max_events_per_frame.is_none_or(|max| relayed < max) {
+        // Anything left over stays in the channel and is picked up first next frame
+        let Some(ev) = session.get_channel_mut().try_recv() else {
+            break;
+        };
+        relayed += 1;
+        // Responses are picked up by the `Session::request` call awaiting them, so they're only recorded here
+        if ev.is_response() {
+            if let Some(recorder) = recorder.as_mut()
+                && let Err(err) = recorder.write(RecordDirection::Incoming, session.get_id(), &ev) {
+                warn!("Failed to record network response: {}", err);
+            }
+            continue;
+        }
         //info!("Relaying network event {}!", ev.get_ev_name());
         network_evs.send(ev);
     }
 }
+
+pub fn relay_overflow_events(session: Res<Session>, mut overflow_evs: EventWriter<ChannelOverflowEvent>) {
+    for ev in session.get_multiplexer().drain_overflow_evs() {
+        overflow_evs.send(ev);
+    }
+}
+
+/// Frees channels whose last receiver dropped and didn't resume within the grace period.
+pub fn expire_parked_channels(session: Res<Session>) {
+    session.get_multiplexer().expire_parked();
+}
+
+/// Applies the `MailboxConfig` resource, if the app inserted one, to the session's multiplexer.
+pub fn apply_mailbox_config(session: Res<Session>, config: Option<Res<MailboxConfig>>) {
+    if let Some(config) = config.filter(|config| config.is_changed()) {
+        session.get_multiplexer().configure_mailbox(Some(config.clone()));
+    }
+}
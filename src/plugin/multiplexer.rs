@@ -13,48 +13,522 @@ use futures_util::Stream;
 
 use uuid::Uuid;
 use std::any::{Any, TypeId};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::future::Future;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::task::{Poll, Context, Waker};
-use anyhow::Result;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "futures")]
+use futures_timer::Delay;
+#[cfg(feature = "futures")]
+use futures_util::future::{select, Either};
+use bevy::platform::time::Instant;
+use serde::{Deserialize, Serialize};
 use crate::prelude::*;
 use bevy::prelude::*;
 
+/// What a multiplexer channel does when its buffer is at capacity and another event is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OverflowPolicy {
+    /// Discards the oldest buffered event to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Discards the event being sent.
+    DropNewest,
+    /// Makes `Multiplexer::send_async` wait until a receiver frees up space.
+    /// Synchronous sends can't wait, so they drop the event being sent instead.
+    Block,
+    /// Disconnects every receiver of the channel and clears its buffer.
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChannelConfig {
+    /// Maximum number of buffered events. `None` means unbounded.
+    pub capacity: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+    /// How long a channel keeps buffering after its last receiver drops, so the peer can resume it.
+    /// `None` stops buffering as soon as nobody is receiving.
+    pub grace_period: Option<Duration>,
+}
+
+impl ChannelConfig {
+    pub fn bounded(capacity: usize, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            capacity: Some(capacity),
+            overflow_policy,
+            ..default()
+        }
+    }
+
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+}
+
+/// Lets a peer pick up its channel where it left off after reconnecting. See `Multiplexer::resume_channel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumeToken {
+    pub peer_id: Id,
+    /// Issued per channel, so a token can't resume a channel that was since recreated.
+    pub token: Id,
+    /// How many events the peer has received from the channel, counted by its `ResumeHandle`. A resume
+    /// replays those after it that are still kept. `None` resumes from where the last receiver stopped reading.
+    #[serde(default)]
+    pub last_index: Option<u64>,
+}
+
+/// Raised whenever a channel's overflow policy is triggered.
+#[derive(Event, Debug, Clone)]
+pub struct ChannelOverflowEvent {
+    pub peer_id: Id,
+    pub policy: OverflowPolicy,
+    pub capacity: usize,
+}
+
+/// Store-and-forward for peers with no receivers. Events sent to them are held in the peer's mailbox
+/// and delivered in order to the next receiver created with `get_channel`.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct MailboxConfig {
+    /// Maximum number of events held per peer. The oldest are dropped first. `None` means unbounded.
+    pub capacity: Option<usize>,
+    /// How long events are held, unless their type has its own expiry. `None` holds them until delivered.
+    pub default_expiry: Option<Duration>,
+    /// Expiry per payload type path, overriding `default_expiry`.
+    pub expiry_by_type: HashMap<String, Option<Duration>>,
+}
+
+impl MailboxConfig {
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn with_default_expiry(mut self, expiry: Duration) -> Self {
+        self.default_expiry = Some(expiry);
+        self
+    }
+
+    /// Sets how long events of type `T` are held. `None` holds them until delivered.
+    pub fn with_expiry<T>(mut self, expiry: Option<Duration>) -> Self where T: TypePath {
+        self.expiry_by_type.insert(T::type_path().to_string(), expiry);
+        self
+    }
+
+    pub fn get_expiry(&self, ev: &NetworkEvent) -> Option<Duration> {
+        ev.type_path()
+            .and_then(|type_path| self.expiry_by_type.get(type_path).copied())
+            .unwrap_or(self.default_expiry)
+    }
+}
+
+/// Changes to mailboxes, recorded so the database layer can persist them.
+#[cfg(feature = "surrealdb")]
+#[derive(Clone)]
+pub enum MailboxEvent {
+    Stored { peer_id: Id, ev: NetworkEvent, expiry: Option<Duration> },
+    /// Every event held for the peer was delivered or expired.
+    Emptied { peer_id: Id },
+}
+
+/// A peer gaining its first live receiver or losing its last one. Parked and temporary receivers don't count as live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceChange {
+    Connected(Id),
+    Disconnected(Id),
+}
+
+/// A peer subscribing to or unsubscribing from a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopicChange {
+    Joined { topic: String, peer_id: Id },
+    Left { topic: String, peer_id: Id },
+}
+
+/// The state of one peer's channel at the time of `Multiplexer::snapshot`.
+#[derive(Debug, Clone)]
+pub struct ChannelSnapshot {
+    pub peer_id: Id,
+    /// Events buffered for receivers that haven't read them yet.
+    pub buffer_depth: usize,
+    /// Events held while the peer had no receivers.
+    pub mailbox_depth: usize,
+    /// Live receivers, not counting a parked one.
+    pub num_receivers: usize,
+    pub is_parked: bool,
+    /// Events sent to the peer since its channel was created.
+    pub total_sent: u64,
+    /// Events read by the peer's receivers since its channel was created.
+    pub total_received: u64,
+    /// How long the oldest event some receiver hasn't read has been waiting.
+    pub oldest_pending_age: Option<Duration>,
+}
+
+/// Event totals across every channel, kept even after channels are removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MultiplexerTotals {
+    pub sent: u64,
+    pub received: u64,
+}
+
+#[derive(Default)]
+struct MultiplexerCounters {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// Number of independently locked maps the multiplexer spreads its channels across.
+const SHARD_COUNT: usize = 32;
+
+/// How many returned events a receiver keeps for replaying after a resume, if its channel is unbounded.
+const DEFAULT_RESUME_WINDOW: usize = 1024;
+
+type SharedChannel = Arc<Mutex<MultiplexerChannel>>;
+type ChannelShard = RwLock<HashMap<Id, SharedChannel>>;
+
+/// Routes events to per-peer channels. The channel map is split into shards so that creating or
+/// removing a channel only locks one shard, and each channel has its own lock so sends and receives
+/// for different peers never contend.
 #[derive(Clone)]
 pub struct Multiplexer {
-    channels: Arc<RwLock<HashMap<Id, MultiplexerChannel>>>
+    shards: Arc<[ChannelShard]>,
+    default_config: ChannelConfig,
+    overflow_evs: Arc<Mutex<Vec<ChannelOverflowEvent>>>,
+    presence_changes: Arc<Mutex<Vec<PresenceChange>>>,
+    /// Subscribers of each named topic.
+    topics: Arc<RwLock<HashMap<String, HashSet<Id>>>>,
+    topic_changes: Arc<Mutex<Vec<TopicChange>>>,
+    /// Checked for events from remote peers. `None` allows everything.
+    authorization: Arc<RwLock<Option<AuthorizationPolicy>>>,
+    rejected_evs: Arc<Mutex<Vec<EventRejected>>>,
+    /// Checked for events from remote peers. `None` doesn't limit them.
+    rate_limiter: Arc<RwLock<Option<Arc<RateLimiter>>>>,
+    rate_limit_counters: Arc<Mutex<HashMap<Id, RateLimitCounters>>>,
+    counters: Arc<MultiplexerCounters>,
+    /// `None` discards events sent to peers with no receivers.
+    mailbox: Arc<RwLock<Option<MailboxConfig>>>,
+    #[cfg(feature = "surrealdb")]
+    mailbox_evs: Arc<Mutex<Vec<MailboxEvent>>>,
+    /// The peer whose sends are collected in `sent_evs`, if any.
+    sent_tap: Arc<RwLock<Option<Id>>>,
+    sent_evs: Arc<Mutex<Vec<(Id, NetworkEvent)>>>,
 }
 
 #[derive(Default)]
 pub struct MultiplexerChannel {
     peer_id: Id,
+    config: ChannelConfig,
     // The last event index for this multiplexer channel
     last_ev: usize,
+    // Incremented on disconnect so receivers from before the disconnect stop receiving
+    generation: usize,
     num_receivers: usize,
-    buffer: Vec<(usize, NetworkEvent)>,
-    /// Registered waker that will be called when a new event is sent.
-    waker: Option<Waker>,
+    /// Receivers that only wait for a single event. Counted in `num_receivers` so events buffer for
+    /// them, but they don't take the peer's mail or count towards its presence.
+    num_temporary: usize,
+    /// Ring buffer of events along with how many receivers still have to read them and when they were buffered.
+    /// Events are only released from the front, once every receiver has read them.
+    buffer: VecDeque<(usize, Instant, NetworkEvent)>,
+    /// Wakers of receivers waiting for a new event, keyed by receiver.
+    wakers: HashMap<usize, Waker>,
+    next_receiver_key: usize,
+    /// Wakers of senders waiting for space in a full buffer.
+    send_wakers: Vec<Waker>,
+    resume_token: Id,
+    /// The last receiver's read position, kept for the grace period after it dropped.
+    /// Counted in `num_receivers`, so events keep buffering for it.
+    parked: Option<ParkedReceiver>,
+    /// Events sent while the peer had no receivers, oldest first.
+    mailbox: VecDeque<Mail>,
+    /// The multiplexer's mailbox config. `None` discards events nobody is left to read.
+    mailbox_config: Arc<RwLock<Option<MailboxConfig>>>,
+    #[cfg(feature = "surrealdb")]
+    mailbox_evs: Arc<Mutex<Vec<MailboxEvent>>>,
+    /// When the peer last sent an event.
+    last_activity: Option<Instant>,
+    /// Events sent to the peer, whether buffered, held as mail or dropped.
+    total_sent: u64,
+    /// Events read by the peer's receivers.
+    total_received: u64,
+}
+
+struct Mail {
+    ev: NetworkEvent,
+    expires_at: Option<Instant>,
+}
+
+struct ParkedReceiver {
+    last_ev: usize,
+    expires_at: Instant,
+    /// See `Channel::delivered` and `Channel::recent`.
+    delivered: u64,
+    recent: VecDeque<NetworkEvent>,
+    /// Events the receiver had stashed but not returned yet.
+    stash: VecDeque<NetworkEvent>,
+}
+
+enum SendResult {
+    Sent,
+    NoReceivers(NetworkEvent),
+    Overflowed(OverflowPolicy),
+    Full(NetworkEvent),
 }
 
 impl MultiplexerChannel {
-    pub fn new(peer_id: Id) -> Self {
+    pub fn new(peer_id: Id, config: ChannelConfig) -> Self {
         Self {
             peer_id,
+            config,
+            resume_token: Id::new(),
             ..default()
         }
     }
 
+    pub fn is_full(&self) -> bool {
+        self.config.capacity.is_some_and(|capacity| self.buffer.len() >= capacity)
+    }
+
     pub fn send_ev(&mut self, ev: NetworkEvent) {
+        // Synchronous senders can't wait for space, so a full blocking channel drops the event
+        self.total_sent += 1;
+        let _ = self.try_send_ev(ev);
+    }
+
+    /// Callers count the event in `total_sent` once, since blocked sends retry this.
+    fn try_send_ev(&mut self, ev: NetworkEvent) -> SendResult {
+        self.expire_parked();
+
+        if self.num_receivers == 0 {
+            //info!("No receivers found! {:?}", ev);
+            return SendResult::NoReceivers(ev);
+        }
+
+        // Temporary receivers see the event, but it's still held for the peer's next real receiver
+        if self.num_receivers == self.num_temporary {
+            if self.is_full() {
+                self.buffer.pop_front();
+            }
+            self.push_ev(ev.clone());
+            return SendResult::NoReceivers(ev);
+        }
+
+        let mut result = SendResult::Sent;
+
+        if self.is_full() {
+            let policy = self.config.overflow_policy;
+            match policy {
+                OverflowPolicy::DropOldest => {
+                    self.buffer.pop_front();
+                    result = SendResult::Overflowed(policy);
+                },
+                OverflowPolicy::DropNewest => {
+                    return SendResult::Overflowed(policy);
+                },
+                OverflowPolicy::Block => {
+                    return SendResult::Full(ev);
+                },
+                OverflowPolicy::Disconnect => {
+                    self.disconnect();
+                    return SendResult::Overflowed(policy);
+                },
+            }
+        }
+
+        self.push_ev(ev);
+
+        result
+    }
+
+    fn push_ev(&mut self, ev: NetworkEvent) {
+        self.last_ev += 1;
+        self.buffer.push_back((self.num_receivers, Instant::now(), ev));
+        self.wake_receivers();
+    }
+
+    fn wake_receivers(&mut self) {
+        for (_, waker) in self.wakers.drain() {
+            waker.wake();
+        }
+    }
+
+    fn add_receiver(&mut self, temporary: bool) -> usize {
+        self.num_receivers += 1;
+        self.num_temporary += temporary as usize;
+        self.next_receiver_key += 1;
+        self.next_receiver_key
+    }
+
+    fn remove_receiver(&mut self, receiver_key: usize, last_ev: usize, temporary: bool) {
+        self.wakers.remove(&receiver_key);
+
         if self.num_receivers > 0 {
-            self.last_ev += 1;
-            self.buffer.push((self.num_receivers, ev));
+            self.num_receivers -= 1;
+        }
+
+        // Release this receiver's share of the events it never read
+        let unread = (self.last_ev - last_ev).min(self.buffer.len());
+        let start = self.buffer.len() - unread;
+        for (lock_count, _, _) in self.buffer.range_mut(start..) {
+            *lock_count = lock_count.saturating_sub(1);
+        }
+
+        // Only temporary receivers are left to read them, so hold them for the peer's next receiver too.
+        // Events sent while only temporary receivers are around are held as they're sent.
+        if !temporary && self.num_receivers == self.num_temporary {
+            let unread: Vec<NetworkEvent> = self.buffer.range(start..).map(|(_, _, ev)| ev.clone()).collect();
+            for ev in unread {
+                self.hold_mail(ev);
+            }
+        }
+        self.release_read_evs();
+    }
+
+    /// Holds `ev` in the mailbox if mailboxes are enabled, otherwise discards it.
+    fn hold_mail(&mut self, ev: NetworkEvent) {
+        let mailbox_config = self.mailbox_config.clone();
+        let mailbox_config = mailbox_config.read().unwrap();
+        let Some(config) = mailbox_config.as_ref() else {
+            return;
+        };
+
+        let expiry = config.get_expiry(&ev);
+        #[cfg(feature = "surrealdb")]
+        self.mailbox_evs.lock().unwrap().push(MailboxEvent::Stored { peer_id: self.peer_id, ev: ev.clone(), expiry });
+        self.store_mail(ev, expiry, config.capacity);
+    }
+
+    /// Receivers that are neither parked nor temporary.
+    fn num_live_receivers(&self) -> usize {
+        self.num_receivers - self.num_temporary - self.parked.is_some() as usize
+    }
+
+    /// Keeps the last receiver's unread events buffered until `parked.expires_at` instead of releasing them.
+    fn park_receiver(&mut self, receiver_key: usize, parked: ParkedReceiver) {
+        self.wakers.remove(&receiver_key);
+        self.parked = Some(parked);
+    }
+
+    /// How many returned events a receiver keeps for replaying after a resume. Only channels with a
+    /// grace period can be resumed, and temporary receivers are never parked.
+    fn resume_window(&self, temporary: bool) -> usize {
+        match self.config.grace_period {
+            Some(_) if !temporary => self.config.capacity.unwrap_or(DEFAULT_RESUME_WINDOW),
+            _ => 0,
+        }
+    }
+
+    /// Releases the parked receiver if its grace period is over. Returns whether it did.
+    fn expire_parked(&mut self) -> bool {
+        let Some(parked) = self.parked.take_if(|parked| parked.expires_at <= Instant::now()) else {
+            return false;
+        };
+        // Receiver keys start at 1, so this doesn't touch any live receiver's waker
+        self.remove_receiver(0, parked.last_ev, false);
+        true
+    }
+
+    /// Turns the parked receiver back into a live one reading from where it stopped, or from the oldest
+    /// buffered event if later ones were already dropped. Returns the new receiver's key and position,
+    /// along with the parked receiver.
+    fn resume_receiver(&mut self) -> Option<(usize, usize, ParkedReceiver)> {
+        self.expire_parked();
+        let parked = self.parked.take()?;
+
+        // The parked receiver is still counted and its unread events still locked, so they carry over as they are
+        let oldest = self.last_ev - self.buffer.len();
+        self.next_receiver_key += 1;
+        Some((self.next_receiver_key, parked.last_ev.max(oldest), parked))
+    }
+
+    /// Holds an event for when the peer next has a receiver, making room within `capacity` if needed.
+    fn store_mail(&mut self, ev: NetworkEvent, expiry: Option<Duration>, capacity: Option<usize>) {
+        self.expire_mail();
+        if capacity.is_some_and(|capacity| self.mailbox.len() >= capacity) {
+            self.mailbox.pop_front();
+        }
+        self.mailbox.push_back(Mail {
+            ev,
+            expires_at: expiry.map(|expiry| Instant::now() + expiry),
+        });
+    }
 
-            if let Some(waker) = self.waker.take() {
+    fn expire_mail(&mut self) {
+        let now = Instant::now();
+        self.mailbox.retain(|mail| mail.expires_at.is_none_or(|expires_at| expires_at > now));
+    }
+
+    /// Moves unexpired mail into the buffer for the current receivers. Returns whether there was any.
+    fn deliver_mail(&mut self) -> bool {
+        if self.mailbox.is_empty() {
+            return false;
+        }
+
+        self.expire_mail();
+        for mail in std::mem::take(&mut self.mailbox) {
+            self.push_ev(mail.ev);
+        }
+        true
+    }
+
+    /// Whether the channel holds nothing worth keeping its entry for.
+    fn is_unused(&self) -> bool {
+        self.num_receivers == 0 && self.buffer.is_empty() && self.mailbox.is_empty()
+    }
+
+    /// Pops events every receiver has read off the front of the buffer.
+    fn release_read_evs(&mut self) {
+        let mut released = false;
+        while self.buffer.front().is_some_and(|(lock_count, _, _)| *lock_count == 0) {
+            self.buffer.pop_front();
+            released = true;
+        }
+
+        if released {
+            for waker in self.send_wakers.drain(..) {
                 waker.wake();
             }
-        } else {
-            //info!("No receivers found! {:?}", ev);
+        }
+    }
+
+    fn register_waker(&mut self, receiver_key: usize, waker: &Waker) {
+        match self.wakers.get_mut(&receiver_key) {
+            Some(existing) if existing.will_wake(waker) => {},
+            Some(existing) => *existing = waker.clone(),
+            None => {
+                self.wakers.insert(receiver_key, waker.clone());
+            }
+        }
+    }
+
+    fn snapshot(&self, now: Instant) -> ChannelSnapshot {
+        ChannelSnapshot {
+            peer_id: self.peer_id,
+            buffer_depth: self.buffer.len(),
+            mailbox_depth: self.mailbox.len(),
+            num_receivers: self.num_live_receivers(),
+            is_parked: self.parked.is_some(),
+            total_sent: self.total_sent,
+            total_received: self.total_received,
+            oldest_pending_age: self.buffer.front().map(|(_, buffered_at, _)| now.duration_since(*buffered_at)),
+        }
+    }
+
+    /// Drops all buffered events and detaches every current receiver.
+    pub fn disconnect(&mut self) {
+        self.generation += 1;
+        self.num_receivers = 0;
+        self.num_temporary = 0;
+        self.parked = None;
+        self.buffer.clear();
+
+        self.wake_receivers();
+        for waker in self.send_wakers.drain(..) {
+            waker.wake();
         }
     }
 
@@ -65,6 +539,12 @@ impl MultiplexerChannel {
             return None;
         }
 
+        // Events this receiver hadn't read yet were dropped, so skip ahead to the oldest buffered one
+        if ev_dif > self.buffer.len() {
+            *last_ev = self.last_ev - self.buffer.len();
+            return self.recv_ev(last_ev);
+        }
+
         //info!("Channel receiver count: {}", self.num_receivers.clone());
         //info!("Channel last ev: {}", self.last_ev.clone());
         //info!("Receiver last ev: {}", last_ev.clone());
@@ -76,45 +556,82 @@ impl MultiplexerChannel {
         //}
 
         let ev_index = self.buffer.len()-ev_dif;
-        let (lock_count, ev) = &mut self.buffer[ev_index];
+        let (lock_count, _, ev) = &mut self.buffer[ev_index];
 
         let ev = ev.clone();
 
-        *lock_count -= 1;
-
-        if *lock_count == 0 {
-            self.buffer.remove(ev_index);
-        }
-        
+        *lock_count = lock_count.saturating_sub(1);
         *last_ev += 1;
+        self.total_received += 1;
+
+        self.release_read_evs();
+
         Some(ev)
     }
 }
 
 pub struct Channel {
     id: Id,
+    /// Identifies this receiver's waker within the multiplexer channel.
+    receiver_key: usize,
+    generation: usize,
     last_ev: usize,
+    /// Only waits for a single event, see `MultiplexerChannel::num_temporary`.
+    temporary: bool,
+    /// Events skipped by a filtered receive, returned before any newly buffered ones.
+    stash: VecDeque<NetworkEvent>,
+    /// Events returned by this receiver and the ones it resumed from, see `ResumeToken::last_index`.
+    delivered: u64,
+    /// The last `resume_window` events returned, so a resume can replay those the peer never received.
+    recent: VecDeque<NetworkEvent>,
+    resume_window: usize,
+    /// The peer's multiplexer channel, held directly so receiving doesn't touch the channel map.
+    channel: SharedChannel,
     multiplexer: Multiplexer
 }
 
 impl Clone for Channel {
     fn clone(&self) -> Self {
-        self.multiplexer.get_channel(self.get_id())
+        self.multiplexer.open_channel(self.get_id(), self.temporary)
     }
 }
 
 // when the streaming body is dropped, this runs
 impl Drop for Channel {
     fn drop(&mut self) {
-        let mut channels = self.multiplexer.channels.write().unwrap();
-        if let Some(ch) = channels.get_mut(&self.id) {
-            // decrement the number of active receivers
-            if ch.num_receivers > 0 {
-                ch.num_receivers -= 1;
+        // Lock the shard first so `get_channel` can't add a receiver to an entry that's being removed
+        let mut shard = self.multiplexer.shard(&self.id).write().unwrap();
+        let mut ch = self.channel.lock().unwrap();
+        let was_live = ch.generation == self.generation && !self.temporary && ch.num_live_receivers() > 0;
+
+        // decrement the number of active receivers, unless the channel already disconnected this one
+        if ch.generation == self.generation {
+            ch.num_temporary -= self.temporary as usize;
+            match ch.config.grace_period {
+                // Keep buffering for the last receiver so its peer can resume after reconnecting
+                Some(grace_period) if !self.temporary && ch.num_live_receivers() == 1 && ch.parked.is_none() => {
+                    ch.park_receiver(self.receiver_key, ParkedReceiver {
+                        last_ev: self.last_ev,
+                        expires_at: Instant::now() + grace_period,
+                        delivered: self.delivered,
+                        recent: std::mem::take(&mut self.recent),
+                        stash: std::mem::take(&mut self.stash),
+                    });
+                },
+                _ => ch.remove_receiver(self.receiver_key, self.last_ev, self.temporary),
             }
-            // optional: if no receivers left and no buffered events, remove the entry
-            if ch.num_receivers == 0 && ch.buffer.is_empty() {
-                channels.remove(&self.id);
+        } else {
+            ch.wakers.remove(&self.receiver_key);
+        }
+
+        if was_live && ch.num_live_receivers() == 0 {
+            self.multiplexer.report_presence(PresenceChange::Disconnected(self.id));
+        }
+
+        // optional: if no receivers left and no buffered events, remove the entry
+        if ch.is_unused() {
+            if shard.get(&self.id).is_some_and(|entry| Arc::ptr_eq(entry, &self.channel)) {
+                shard.remove(&self.id);
             }
         }
     }
@@ -126,21 +643,33 @@ impl Channel {
         self.id.clone()
     }
 
+    /// A token for resuming this channel once this receiver drops, counting the events returned so far.
+    pub fn resume_token(&self) -> ResumeToken {
+        ResumeToken {
+            peer_id: self.id,
+            token: self.channel.lock().unwrap().resume_token,
+            last_index: Some(self.delivered),
+        }
+    }
+
+    /// Whether the underlying multiplexer channel disconnected this receiver.
+    pub fn is_disconnected(&self) -> bool {
+        self.channel.lock().unwrap().generation != self.generation
+    }
+
     pub fn send_ev<T>(&self, recipient_id: Id, ev: T) where T: Struct {
         self.multiplexer.send(recipient_id, NetworkEvent::new(self.id.clone(), ev));
     }
 
     pub fn try_recv(&mut self) -> Option<NetworkEvent> {
-        let mut channels = self.multiplexer.channels.write().unwrap();
-
-        let mut channel = channels.get_mut(&self.id.clone()).expect(&format!("Failed to get peer event buffer for ID {:#}", self.id));
-
-        //dbg!(self.peer_id.clone());
-        if let Some(ev) = channel.recv_ev(&mut self.last_ev) {
-            return Some(ev);
-        }
-
-        None
+        let ev = match self.stash.pop_front() {
+            Some(ev) => Some(ev),
+            None => match self.poll_buffered(None) {
+                Poll::Ready(ev) => ev,
+                Poll::Pending => None
+            },
+        };
+        self.deliver(ev)
         /*
         let sender = self.map.read().unwrap().get(&recv_id).unwrap().to_owned();
         
@@ -151,46 +680,446 @@ impl Channel {
         None
         */
     }
-}
 
-#[cfg(feature = "futures")]
-impl Stream for Channel {
-    type Item = NetworkEvent;
+    /// Polls for the next event, registering the task's waker if none is buffered.
+    /// Resolves to `None` once the channel has disconnected this receiver.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<NetworkEvent>> {
+        let ev = match self.stash.pop_front() {
+            Some(ev) => Poll::Ready(Some(ev)),
+            None => self.poll_buffered(Some(cx)),
+        };
+        ev.map(|ev| self.deliver(ev))
+    }
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    /// Waits for the next event without spinning.
+    pub async fn recv(&mut self) -> Option<NetworkEvent> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Waits for the first event matching `filter`. Events that don't match are kept in order
+    /// for later calls to `recv`, `try_recv` or the stream instead of being dropped.
+    pub async fn recv_filtered<F>(&mut self, mut filter: F) -> Option<NetworkEvent> where F: FnMut(&NetworkEvent) -> bool {
+        if let Some(index) = self.stash.iter().position(|ev| filter(ev)) {
+            let ev = self.stash.remove(index);
+            return self.deliver(ev);
+        }
+
+        loop {
+            let ev = std::future::poll_fn(|cx| self.poll_buffered(Some(cx))).await?;
+            if filter(&ev) {
+                return self.deliver(Some(ev));
+            }
+            self.stash.push_back(ev);
+        }
+    }
+
+    /// Waits for an event of type `T` sent by `sender_id`, leaving other events in the channel.
+    pub async fn recv_ev_from<T>(&mut self, sender_id: Id) -> Result<T> where T: Reflect + FromReflect + Typed {
+        let network_ev = self.recv_filtered(|ev| ev.peer_id == sender_id && ev.is_ev::<T>()).await
+            .ok_or_else(|| anyhow!("Channel {:#} was disconnected while waiting for {}", self.id, T::short_type_path()))?;
+
+        network_ev.get_ev::<T>().ok_or_else(|| anyhow!("Failed to convert network event to {}", T::short_type_path()))
+    }
+
+    #[cfg(feature = "futures")]
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<NetworkEvent>> {
+        with_timeout(self.recv(), timeout).await
+    }
+
+    /// Counts an event being returned, keeping it for replaying after a resume.
+    fn deliver(&mut self, ev: Option<NetworkEvent>) -> Option<NetworkEvent> {
+        if let Some(ev) = &ev {
+            self.delivered += 1;
+            if self.resume_window > 0 {
+                if self.recent.len() >= self.resume_window {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(ev.clone());
+            }
+        }
+        ev
+    }
+
+    fn poll_buffered(&mut self, cx: Option<&mut Context<'_>>) -> Poll<Option<NetworkEvent>> {
         // Acquire the lock on the underlying multiplexer channel.
-        let multiplxer = self.multiplexer.clone();
-        let mut channels = multiplxer.channels.write().unwrap();
-        let ch = channels.get_mut(&self.id).expect(&format!("Failed to get peer event buffer for ID {:#}", self.id));
-        
+        let channel = self.channel.clone();
+        let mut ch = channel.lock().unwrap();
+
+        // The channel overflowed with the disconnect policy, so this receiver is done
+        if ch.generation != self.generation {
+            return Poll::Ready(None);
+        }
+
         // First check: try to receive an event.
         //info!("Poll receiving...");
         if let Some(ev) = ch.recv_ev(&mut self.last_ev) {
+            self.multiplexer.counters.received.fetch_add(1, Ordering::Relaxed);
             return Poll::Ready(Some(ev));
         }
-        
-        // No event yet: register the waker.
-        ch.waker = Some(cx.waker().clone());
-        
-        /*
-        // Double-check: an event could have been sent right after the previous check.
-        info!("Waker woke up, receiving...");
-        if let Some(ev) = ch.recv_ev(self.last_ev) {
-            self.last_ev += 1;
-            // Clear the waker because we are about to return an event.
-            ch.waker = None;
-            return Poll::Ready(Some(ev));
-        } */
-        
+
+        // No event yet: register the waker. Since it's registered under the same lock senders take,
+        // an event can't slip in between the check above and the registration.
+        if let Some(cx) = cx {
+            ch.register_waker(self.receiver_key, cx.waker());
+        }
+
         Poll::Pending
     }
 }
 
+#[cfg(feature = "futures")]
+impl Stream for Channel {
+    type Item = NetworkEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx)
+    }
+}
+
+/// Resolves `future`, or fails once `timeout` has elapsed.
+#[cfg(feature = "futures")]
+pub async fn with_timeout<F>(future: F, timeout: Duration) -> Result<F::Output> where F: Future {
+    let future = std::pin::pin!(future);
+    match select(future, Delay::new(timeout)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(anyhow!("Timed out after {:?}", timeout)),
+    }
+}
+
 impl Multiplexer {
     pub fn new() -> Self {
+        Self::with_config(ChannelConfig::default())
+    }
+
+    /// Creates a multiplexer whose channels use the given config unless overridden with `configure_channel`.
+    pub fn with_config(default_config: ChannelConfig) -> Self {
         Self {
             //map: Arc::new(RwLock::new(HashMap::new())),
-            channels: Arc::new(RwLock::new(HashMap::new()))
+            shards: (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect(),
+            default_config,
+            overflow_evs: Default::default(),
+            presence_changes: Default::default(),
+            topics: Default::default(),
+            topic_changes: Default::default(),
+            authorization: Default::default(),
+            rejected_evs: Default::default(),
+            rate_limiter: Default::default(),
+            rate_limit_counters: Default::default(),
+            counters: Default::default(),
+            mailbox: Default::default(),
+            #[cfg(feature = "surrealdb")]
+            mailbox_evs: Default::default(),
+            sent_tap: Default::default(),
+            sent_evs: Default::default(),
+        }
+    }
+
+    /// Enables holding events for peers with no receivers, or disables it with `None`.
+    /// Disabling it keeps what's already held until it's delivered or expires.
+    pub fn configure_mailbox(&self, config: Option<MailboxConfig>) {
+        *self.mailbox.write().unwrap() = config;
+    }
+
+    /// A new channel for `peer_id` that shares this multiplexer's mailbox.
+    fn new_channel(&self, peer_id: Id) -> SharedChannel {
+        Arc::new(Mutex::new(MultiplexerChannel {
+            mailbox_config: self.mailbox.clone(),
+            #[cfg(feature = "surrealdb")]
+            mailbox_evs: self.mailbox_evs.clone(),
+            ..MultiplexerChannel::new(peer_id, self.default_config)
+        }))
+    }
+
+    /// Puts back mail loaded from storage, e.g. after a restart. It's delivered like any other held event.
+    pub fn restore_mail(&self, peer_id: Id, ev: NetworkEvent, expiry: Option<Duration>) {
+        let capacity = self.mailbox.read().unwrap().as_ref().and_then(|config| config.capacity);
+        self.with_channel(peer_id, |channel| channel.store_mail(ev, expiry, capacity));
+    }
+
+    /// Takes the mailbox changes recorded since the last call.
+    #[cfg(feature = "surrealdb")]
+    pub fn drain_mailbox_evs(&self) -> Vec<MailboxEvent> {
+        std::mem::take(&mut *self.mailbox_evs.lock().unwrap())
+    }
+
+    fn shard(&self, peer_id: &Id) -> &ChannelShard {
+        let mut hasher = DefaultHasher::new();
+        peer_id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// Runs `f` on the peer's channel, creating it if there isn't one. The shard lock is held until `f`
+    /// returns, the same order `Channel::drop` locks in, so the entry can't be removed in between
+    /// and leave `f` writing to a channel no receiver will ever read.
+    fn with_channel<R>(&self, peer_id: Id, f: impl FnOnce(&mut MultiplexerChannel) -> R) -> R {
+        let shard = self.shard(&peer_id);
+        {
+            let channels = shard.read().unwrap();
+            if let Some(channel) = channels.get(&peer_id) {
+                return f(&mut channel.lock().unwrap());
+            }
+        }
+
+        let mut channels = shard.write().unwrap();
+        let channel = channels
+            .entry(peer_id)
+            .or_insert_with(|| self.new_channel(peer_id));
+        f(&mut channel.lock().unwrap())
+    }
+
+    pub fn configure_channel(&self, peer_id: Id, config: ChannelConfig) {
+        self.with_channel(peer_id, |channel| channel.config = config);
+    }
+
+    /// Takes the overflow events raised since the last call.
+    pub fn drain_overflow_evs(&self) -> Vec<ChannelOverflowEvent> {
+        std::mem::take(&mut *self.overflow_evs.lock().unwrap())
+    }
+
+    /// Collects every event `sender_id` sends, along with its recipient, for `drain_sent_evs`.
+    /// `None` stops collecting.
+    pub fn tap_sent(&self, sender_id: Option<Id>) {
+        *self.sent_tap.write().unwrap() = sender_id;
+        if sender_id.is_none() {
+            self.sent_evs.lock().unwrap().clear();
+        }
+    }
+
+    /// Takes the events collected by `tap_sent` since the last call.
+    pub fn drain_sent_evs(&self) -> Vec<(Id, NetworkEvent)> {
+        std::mem::take(&mut *self.sent_evs.lock().unwrap())
+    }
+
+    fn tap(&self, recipient_id: Id, ev: &NetworkEvent) {
+        if *self.sent_tap.read().unwrap() == Some(ev.peer_id) {
+            self.sent_evs.lock().unwrap().push((recipient_id, ev.clone()));
+        }
+    }
+
+    fn report_overflow(&self, peer_id: Id, policy: OverflowPolicy, config: &ChannelConfig) {
+        if policy == OverflowPolicy::Disconnect {
+            self.report_presence(PresenceChange::Disconnected(peer_id));
+        }
+        self.overflow_evs.lock().unwrap().push(ChannelOverflowEvent {
+            peer_id,
+            policy,
+            capacity: config.capacity.unwrap_or_default(),
+        });
+    }
+
+    /// The state of every channel, taking each shard's lock in turn rather than all at once.
+    pub fn snapshot(&self) -> Vec<ChannelSnapshot> {
+        let now = Instant::now();
+        let mut snapshots = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap();
+            snapshots.extend(shard.values().map(|channel| channel.lock().unwrap().snapshot(now)));
+        }
+        snapshots
+    }
+
+    pub fn get_totals(&self) -> MultiplexerTotals {
+        MultiplexerTotals {
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            received: self.counters.received.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Takes the presence changes recorded since the last call, in the order they happened.
+    pub fn drain_presence_changes(&self) -> Vec<PresenceChange> {
+        std::mem::take(&mut *self.presence_changes.lock().unwrap())
+    }
+
+    fn report_presence(&self, change: PresenceChange) {
+        if let PresenceChange::Disconnected(peer_id) = change
+            && let Some(rate_limiter) = self.rate_limiter.read().unwrap().as_ref() {
+            rate_limiter.forget_peer(peer_id);
+        }
+        self.presence_changes.lock().unwrap().push(change);
+    }
+
+    pub fn set_authorization_policy(&self, policy: Option<AuthorizationPolicy>) {
+        *self.authorization.write().unwrap() = policy;
+    }
+
+    /// Replaces the limits on events from remote peers. Token buckets start over, counters are kept.
+    pub fn set_rate_limits(&self, config: Option<RateLimitConfig>) {
+        *self.rate_limiter.write().unwrap() = config.map(|config| Arc::new(RateLimiter::new(config)));
+    }
+
+    /// Incoming event counters for each remote peer that has sent something while limits were set.
+    pub fn get_rate_limit_counters(&self) -> HashMap<Id, RateLimitCounters> {
+        self.rate_limit_counters.lock().unwrap().clone()
+    }
+
+    fn count_rate_limit(&self, peer_id: Id, count: impl FnOnce(&mut RateLimitCounters)) {
+        count(self.rate_limit_counters.lock().unwrap().entry(peer_id).or_default());
+    }
+
+    /// Delivers an event that arrived from a remote peer, unless the authorization policy or rate
+    /// limits reject it. Returns whether it was delivered. Fails if the sender exceeded a limit whose
+    /// action is `RateLimitAction::Disconnect`, so the caller can close its connection.
+    #[cfg(feature = "futures")]
+    pub async fn send_remote(&self, recipient_id: Id, ev: NetworkEvent) -> Result<bool> {
+        let authorized = self.authorization.read().unwrap().as_ref()
+            .is_none_or(|policy| policy.is_authorized(ev.peer_id, recipient_id, &ev));
+        if !authorized {
+            self.reject(recipient_id, &ev, RejectReason::Unauthorized);
+            return Ok(false);
+        }
+
+        let mut delayed = false;
+        loop {
+            let rate_limiter = self.rate_limiter.read().unwrap().clone();
+            let Some(rate_limiter) = rate_limiter else {
+                break;
+            };
+
+            let wait = match rate_limiter.try_acquire(&ev) {
+                Ok(()) => {
+                    self.count_rate_limit(ev.peer_id, |counters| counters.allowed += 1);
+                    break;
+                },
+                Err(wait) => wait,
+            };
+
+            match rate_limiter.get_action() {
+                // A bucket that never refills can't be waited on
+                RateLimitAction::Delay if wait != Duration::MAX => {
+                    if !delayed {
+                        self.count_rate_limit(ev.peer_id, |counters| counters.delayed += 1);
+                        delayed = true;
+                    }
+                    Delay::new(wait).await;
+                },
+                RateLimitAction::Disconnect => {
+                    self.count_rate_limit(ev.peer_id, |counters| counters.disconnected += 1);
+                    self.reject(recipient_id, &ev, RejectReason::RateLimited);
+                    return Err(anyhow!("Peer {:#} exceeded its rate limit", ev.peer_id));
+                },
+                _ => {
+                    self.count_rate_limit(ev.peer_id, |counters| counters.dropped += 1);
+                    self.reject(recipient_id, &ev, RejectReason::RateLimited);
+                    return Ok(false);
+                },
+            }
+        }
+
+        self.send_async(recipient_id, ev).await;
+        Ok(true)
+    }
+
+    pub(crate) fn reject(&self, recipient_id: Id, ev: &NetworkEvent, reason: RejectReason) {
+        self.rejected_evs.lock().unwrap().push(EventRejected {
+            sender_id: ev.peer_id,
+            recipient_id,
+            type_path: ev.type_path().map(|type_path| type_path.to_string()),
+            reason,
+        });
+    }
+
+    /// Takes the audit events for incoming events rejected since the last call.
+    pub fn drain_rejected_evs(&self) -> Vec<EventRejected> {
+        std::mem::take(&mut *self.rejected_evs.lock().unwrap())
+    }
+
+    /// Subscribes `peer_id` to `topic`, so it receives everything published there.
+    pub fn subscribe(&self, topic: &str, peer_id: Id) {
+        let joined = self.topics.write().unwrap().entry(topic.to_string()).or_default().insert(peer_id);
+        if joined {
+            self.topic_changes.lock().unwrap().push(TopicChange::Joined { topic: topic.to_string(), peer_id });
+        }
+    }
+
+    pub fn unsubscribe(&self, topic: &str, peer_id: Id) {
+        let mut topics = self.topics.write().unwrap();
+        let Some(subscribers) = topics.get_mut(topic) else {
+            return;
+        };
+
+        if subscribers.remove(&peer_id) {
+            if subscribers.is_empty() {
+                topics.remove(topic);
+            }
+            self.topic_changes.lock().unwrap().push(TopicChange::Left { topic: topic.to_string(), peer_id });
+        }
+    }
+
+    /// Unsubscribes `peer_id` from every topic, e.g. once it has left for good.
+    pub fn unsubscribe_all(&self, peer_id: Id) {
+        let topics: Vec<String> = self.topics.read().unwrap().iter()
+            .filter(|(_, subscribers)| subscribers.contains(&peer_id))
+            .map(|(topic, _)| topic.clone())
+            .collect();
+
+        for topic in topics {
+            self.unsubscribe(&topic, peer_id);
+        }
+    }
+
+    pub fn get_subscribers(&self, topic: &str) -> Vec<Id> {
+        self.topics.read().unwrap().get(topic).map(|subscribers| subscribers.iter().copied().collect()).unwrap_or_default()
+    }
+
+    /// Topics `peer_id` is subscribed to.
+    pub fn get_topics(&self, peer_id: Id) -> Vec<String> {
+        self.topics.read().unwrap().iter()
+            .filter(|(_, subscribers)| subscribers.contains(&peer_id))
+            .map(|(topic, _)| topic.clone())
+            .collect()
+    }
+
+    /// Sends `ev` to every subscriber of `topic` except its sender.
+    pub fn publish(&self, topic: &str, ev: NetworkEvent) {
+        // Collected first so sends don't hold the topic lock
+        for subscriber_id in self.get_subscribers(topic) {
+            if subscriber_id != ev.peer_id {
+                self.send(subscriber_id, ev.clone());
+            }
+        }
+    }
+
+    /// Like `publish`, but only to subscribers the `AuthorizationPolicy` allows `ev`'s sender to reach.
+    /// For events other peers asked to publish.
+    pub fn publish_authorized(&self, topic: &str, ev: NetworkEvent) {
+        let policy = self.authorization.read().unwrap().clone();
+        for subscriber_id in self.get_subscribers(topic) {
+            if subscriber_id == ev.peer_id {
+                continue;
+            }
+            if policy.as_ref().is_none_or(|policy| policy.is_authorized(ev.peer_id, subscriber_id, &ev)) {
+                self.send(subscriber_id, ev.clone());
+            } else {
+                self.reject(subscriber_id, &ev, RejectReason::Unauthorized);
+            }
+        }
+    }
+
+    pub fn publish_ev<T>(&self, sender_id: Id, topic: &str, ev: T) where T: Struct {
+        self.publish(topic, NetworkEvent::new(sender_id, ev));
+    }
+
+    /// Takes the topic membership changes recorded since the last call, in the order they happened.
+    pub fn drain_topic_changes(&self) -> Vec<TopicChange> {
+        std::mem::take(&mut *self.topic_changes.lock().unwrap())
+    }
+
+    /// When `peer_id` last sent an event, if it has a channel.
+    pub fn get_last_activity(&self, peer_id: Id) -> Option<Instant> {
+        let shard = self.shard(&peer_id).read().unwrap();
+        shard.get(&peer_id).and_then(|channel| channel.lock().unwrap().last_activity)
+    }
+
+    /// Whether `peer_id` has a channel, i.e. receivers, a parked receiver, buffered events or mail.
+    pub fn has_channel(&self, peer_id: Id) -> bool {
+        self.shard(&peer_id).read().unwrap().contains_key(&peer_id)
+    }
+
+    /// Notes that `peer_id` sent an event. Peers without a channel aren't tracked.
+    fn record_activity(&self, peer_id: Id) {
+        let shard = self.shard(&peer_id).read().unwrap();
+        if let Some(channel) = shard.get(&peer_id) {
+            channel.lock().unwrap().last_activity = Some(Instant::now());
         }
     }
 
@@ -203,18 +1132,92 @@ impl Multiplexer {
     }*/
 
     pub fn get_channel(&self, peer_id: Id) -> Channel {
-        let mut channels = self.channels.write().unwrap();
-        let mut channel = channels.entry(peer_id.clone()).or_insert_with(|| MultiplexerChannel::new(peer_id.clone()));
-        channel.num_receivers += 1;
+        self.open_channel(peer_id, false)
+    }
+
+    /// Adds a receiver for `peer_id`. Temporary receivers are for waiting on a single event, so
+    /// they leave the peer's mail and presence to its real connections.
+    fn open_channel(&self, peer_id: Id, temporary: bool) -> Channel {
+        // Hold the shard lock while adding the receiver so a dropping channel can't remove the entry in between
+        let mut shard = self.shard(&peer_id).write().unwrap();
+        let shared_channel = shard.entry(peer_id.clone()).or_insert_with(|| self.new_channel(peer_id)).clone();
+        let mut channel = shared_channel.lock().unwrap();
+        let receiver_key = channel.add_receiver(temporary);
         //info!("Added receiver for {}.", peer_id);
-        
+
+        if !temporary && channel.num_live_receivers() == 1 {
+            self.report_presence(PresenceChange::Connected(peer_id));
+        }
+
+        // The first real receiver gets whatever was held for the peer while it had none
+        let last_ev = channel.last_ev;
+        if !temporary && channel.num_live_receivers() == 1 && channel.parked.is_none() && channel.deliver_mail() {
+            #[cfg(feature = "surrealdb")]
+            self.mailbox_evs.lock().unwrap().push(MailboxEvent::Emptied { peer_id });
+        }
+
         Channel {
-            last_ev: channel.last_ev,
+            receiver_key,
+            generation: channel.generation,
+            last_ev,
+            temporary,
+            stash: VecDeque::new(),
+            delivered: 0,
+            recent: VecDeque::new(),
+            resume_window: channel.resume_window(temporary),
+            channel: shared_channel.clone(),
             id: peer_id,
             multiplexer: self.clone()
         }
     }
 
+    /// Resumes a channel whose last receiver dropped within its grace period, replaying the events
+    /// that receiver hadn't read yet. With `token.last_index`, the events it returned after that index
+    /// are replayed first, as far back as it kept them. Returns `None` if the token doesn't match or
+    /// the grace period is over, in which case use `get_channel` instead.
+    pub fn resume_channel(&self, token: &ResumeToken) -> Option<Channel> {
+        let shard = self.shard(&token.peer_id).write().unwrap();
+        let shared_channel = shard.get(&token.peer_id)?.clone();
+        let mut channel = shared_channel.lock().unwrap();
+        if channel.resume_token != token.token {
+            return None;
+        }
+        let (receiver_key, last_ev, parked) = channel.resume_receiver()?;
+        if channel.num_live_receivers() == 1 {
+            self.report_presence(PresenceChange::Connected(token.peer_id));
+        }
+
+        let missed = token.last_index.map_or(0, |last_index| parked.delivered.saturating_sub(last_index) as usize);
+        let mut recent = parked.recent;
+        let mut stash = recent.split_off(recent.len() - missed.min(recent.len()));
+        let delivered = parked.delivered - stash.len() as u64;
+        stash.extend(parked.stash);
+
+        Some(Channel {
+            receiver_key,
+            generation: channel.generation,
+            last_ev,
+            temporary: false,
+            stash,
+            delivered,
+            recent,
+            resume_window: channel.resume_window(false),
+            channel: shared_channel.clone(),
+            id: token.peer_id,
+            multiplexer: self.clone()
+        })
+    }
+
+    /// Releases parked receivers whose grace period is over, removing channels left with nothing to hold.
+    pub fn expire_parked(&self) {
+        for shard in self.shards.iter() {
+            shard.write().unwrap().retain(|_, channel| {
+                let mut channel = channel.lock().unwrap();
+                !(channel.expire_parked() && channel.is_unused())
+            });
+        }
+    }
+
     /*
     // TODO: move into helper function elsewhere
     // Used for checking if type T is a certian variant
@@ -262,44 +1265,270 @@ impl Multiplexer {
         //let sender = self.map.read().unwrap().get(&recv_id).unwrap()./to_owned();
         //sender.send(ev.clone()).unwrap();
 
-        let mut channels = self.channels.write().unwrap();
-        channels.entry(recipient_id.clone()).or_insert_with(move || MultiplexerChannel::new(recipient_id)).send_ev(ev);
+        // Before locking the recipient, which may be the sender itself
+        self.record_activity(ev.peer_id);
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.tap(recipient_id, &ev);
+
+        self.with_channel(recipient_id, |channel| {
+            channel.total_sent += 1;
+            match channel.try_send_ev(ev) {
+                SendResult::Sent => {},
+                SendResult::NoReceivers(ev) => channel.hold_mail(ev),
+                SendResult::Overflowed(policy) => self.report_overflow(recipient_id, policy, &channel.config),
+                // Can't wait here, so the event is dropped
+                SendResult::Full(_) => self.report_overflow(recipient_id, OverflowPolicy::Block, &channel.config),
+            }
+        });
+    }
+
+    /// Sends an event, waiting for buffer space if the recipient's channel is full and uses `OverflowPolicy::Block`.
+    pub async fn send_async(&self, recipient_id: Id, ev: NetworkEvent) {
+        self.record_activity(ev.peer_id);
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.tap(recipient_id, &ev);
+
+        let mut ev = Some(ev);
+        let mut reported = false;
+        self.with_channel(recipient_id, |channel| channel.total_sent += 1);
+
+        std::future::poll_fn(|cx| self.with_channel(recipient_id, |channel| {
+            match channel.try_send_ev(ev.take().unwrap()) {
+                SendResult::Sent => Poll::Ready(()),
+                SendResult::NoReceivers(ev) => {
+                    channel.hold_mail(ev);
+                    Poll::Ready(())
+                },
+                SendResult::Overflowed(policy) => {
+                    self.report_overflow(recipient_id, policy, &channel.config);
+                    Poll::Ready(())
+                },
+                SendResult::Full(full_ev) => {
+                    // Only report the first time this send gets blocked
+                    if !reported {
+                        self.report_overflow(recipient_id, OverflowPolicy::Block, &channel.config);
+                        reported = true;
+                    }
+                    ev = Some(full_ev);
+                    channel.send_wakers.push(cx.waker().clone());
+                    Poll::Pending
+                },
+            }
+        })).await
     }
 
     pub fn send_ev<T>(&self, sender_id: Id, receiver_id: Id, ev: T) where T: Struct {
         self.send(receiver_id, NetworkEvent::new(sender_id, ev));
     }
 
+    /// Waits for an event of type `T` from `sender_id` on a temporary receiver for `receiver_id`.
+    /// Other events stay available to the peer's existing receivers, or held as its mail.
     pub async fn recv_ev<T>(&self, receiver_id: Id, sender_id: Id) -> Result<T> where T: Reflect + FromReflect + Typed {
-        let mut rx = self.get_channel(receiver_id);
-        loop {
-            if let Some(network_ev) = rx.try_recv() {
-                if network_ev.peer_id == sender_id {
+        let mut rx = self.open_channel(receiver_id, true);
+        rx.recv_ev_from::<T>(sender_id).await
+    }
 
-                    if let Some(ev) = network_ev.get_ev::<T>() {
-                        return Ok(ev);
-                    }
+    #[cfg(feature = "futures")]
+    pub async fn recv_ev_timeout<T>(&self, receiver_id: Id, sender_id: Id, timeout: Duration) -> Result<T> where T: Reflect + FromReflect + Typed {
+        let mut rx = self.open_channel(receiver_id, true);
+        with_timeout(rx.recv_ev_from::<T>(sender_id), timeout).await?
+    }
+}
 
-                    /*
-                    if let ReflectRef::Enum(enum_ref) = ev.reflect_ref() {
-                        let s = enum_ref.field_at(0).unwrap();
-
-                        if let TypeInfo::Struct(_struct_info) = T::type_info()
-                        {
-                            if _struct_info.type_path() == s.reflect_type_path() {
-
-                                let mut t = T::from_reflect(s).unwrap();
-                   
-                                //let mut t = T::default();
-                                //t.apply(s);
-                                
-                                return Ok(t); 
-                            }
-                        }
-                    }
-                    */
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use bevy::reflect::GetField;
+    use super::*;
+
+    #[derive(Reflect, Clone, Debug)]
+    struct Ping {
+        n: u32,
+    }
+
+    fn ping(n: u32) -> NetworkEvent {
+        NetworkEvent::new(Id::new(), Ping { n })
+    }
+
+    fn get_n(ev: &NetworkEvent) -> u32 {
+        *ev.ev.get_field::<u32>("n").unwrap()
+    }
+
+    fn buffer_len(multiplexer: &Multiplexer, peer_id: Id) -> usize {
+        multiplexer.with_channel(peer_id, |channel| channel.buffer.len())
+    }
+
+    #[test]
+    fn events_are_released_once_every_receiver_read_them() {
+        let multiplexer = Multiplexer::new();
+        let peer_id = Id::new();
+        let mut a = multiplexer.get_channel(peer_id);
+        let mut b = multiplexer.get_channel(peer_id);
+
+        multiplexer.send(peer_id, ping(1));
+        multiplexer.send(peer_id, ping(2));
+
+        assert_eq!(a.try_recv().map(|ev| get_n(&ev)), Some(1));
+        assert_eq!(a.try_recv().map(|ev| get_n(&ev)), Some(2));
+        assert!(a.try_recv().is_none());
+        assert_eq!(buffer_len(&multiplexer, peer_id), 2);
+
+        assert_eq!(b.try_recv().map(|ev| get_n(&ev)), Some(1));
+        assert_eq!(buffer_len(&multiplexer, peer_id), 1);
+        assert_eq!(b.try_recv().map(|ev| get_n(&ev)), Some(2));
+        assert_eq!(buffer_len(&multiplexer, peer_id), 0);
+    }
+
+    #[test]
+    fn dropped_receiver_releases_its_unread_events() {
+        let multiplexer = Multiplexer::new();
+        let peer_id = Id::new();
+        let mut a = multiplexer.get_channel(peer_id);
+        let b = multiplexer.get_channel(peer_id);
+
+        multiplexer.send(peer_id, ping(1));
+        multiplexer.send(peer_id, ping(2));
+        assert!(a.try_recv().is_some());
+
+        drop(b);
+        assert_eq!(buffer_len(&multiplexer, peer_id), 1);
+        assert!(a.try_recv().is_some());
+        assert_eq!(buffer_len(&multiplexer, peer_id), 0);
+    }
+
+    #[test]
+    fn drop_oldest_skips_receivers_ahead() {
+        let multiplexer = Multiplexer::with_config(ChannelConfig::bounded(2, OverflowPolicy::DropOldest));
+        let peer_id = Id::new();
+        let mut rx = multiplexer.get_channel(peer_id);
+
+        for n in 1..=3 {
+            multiplexer.send(peer_id, ping(n));
         }
+
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(2));
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(3));
+        assert!(rx.try_recv().is_none());
+        assert_eq!(multiplexer.drain_overflow_evs().len(), 1);
+    }
+
+    #[test]
+    fn disconnect_policy_detaches_receivers() {
+        let multiplexer = Multiplexer::with_config(ChannelConfig::bounded(1, OverflowPolicy::Disconnect));
+        let peer_id = Id::new();
+        let mut rx = multiplexer.get_channel(peer_id);
+
+        multiplexer.send(peer_id, ping(1));
+        multiplexer.send(peer_id, ping(2));
+
+        assert!(rx.is_disconnected());
+        assert!(rx.try_recv().is_none());
+        assert_eq!(buffer_len(&multiplexer, peer_id), 0);
+    }
+
+    #[test]
+    fn sends_reach_a_channel_recreated_after_its_last_receiver_dropped() {
+        let multiplexer = Multiplexer::new();
+        let peer_id = Id::new();
+        drop(multiplexer.get_channel(peer_id));
+
+        let mut rx = multiplexer.get_channel(peer_id);
+        multiplexer.send(peer_id, ping(1));
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(1));
+    }
+
+    #[test]
+    fn parked_receiver_resumes_from_its_last_read_event() {
+        let multiplexer = Multiplexer::with_config(ChannelConfig {
+            grace_period: Some(Duration::from_secs(60)),
+            ..default()
+        });
+        let peer_id = Id::new();
+        let mut rx = multiplexer.get_channel(peer_id);
+
+        multiplexer.send(peer_id, ping(1));
+        assert!(rx.try_recv().is_some());
+        let token = rx.resume_token();
+        drop(rx);
+
+        multiplexer.send(peer_id, ping(2));
+        let mut rx = multiplexer.resume_channel(&token).unwrap();
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(2));
+        assert!(rx.try_recv().is_none());
+        assert_eq!(buffer_len(&multiplexer, peer_id), 0);
+    }
+
+    #[test]
+    fn resume_replays_what_the_peer_never_received() {
+        let multiplexer = Multiplexer::with_config(ChannelConfig::default().with_grace_period(Duration::from_secs(60)));
+        let peer_id = Id::new();
+        let mut rx = multiplexer.get_channel(peer_id);
+
+        multiplexer.send(peer_id, ping(1));
+        multiplexer.send(peer_id, ping(2));
+        assert!(rx.try_recv().is_some());
+        assert!(rx.try_recv().is_some());
+        // The peer only got the first event before the connection dropped
+        let token = ResumeToken { last_index: Some(1), ..rx.resume_token() };
+        drop(rx);
+
+        multiplexer.send(peer_id, ping(3));
+        let mut rx = multiplexer.resume_channel(&token).unwrap();
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(2));
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(3));
+        assert!(rx.try_recv().is_none());
+        assert_eq!(rx.resume_token().last_index, Some(3));
+    }
+
+    #[test]
+    fn temporary_receivers_leave_mail_and_presence_alone() {
+        let multiplexer = Multiplexer::new();
+        multiplexer.configure_mailbox(Some(MailboxConfig::default()));
+        let peer_id = Id::new();
+
+        let mut temporary = multiplexer.open_channel(peer_id, true);
+        multiplexer.send(peer_id, ping(1));
+        assert_eq!(temporary.try_recv().map(|ev| get_n(&ev)), Some(1));
+        drop(temporary);
+        assert!(multiplexer.drain_presence_changes().is_empty());
+
+        // The event the temporary receiver saw is still held for the peer's connection
+        let mut rx = multiplexer.get_channel(peer_id);
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(1));
+        assert!(matches!(multiplexer.drain_presence_changes()[..], [PresenceChange::Connected(_)]));
+    }
+
+    #[test]
+    fn unread_events_are_held_as_mail_when_the_last_receiver_drops() {
+        let multiplexer = Multiplexer::new();
+        multiplexer.configure_mailbox(Some(MailboxConfig::default()));
+        let peer_id = Id::new();
+
+        let mut rx = multiplexer.get_channel(peer_id);
+        multiplexer.send(peer_id, ping(1));
+        multiplexer.send(peer_id, ping(2));
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(1));
+        drop(rx);
+        assert_eq!(buffer_len(&multiplexer, peer_id), 0);
+
+        let mut rx = multiplexer.get_channel(peer_id);
+        assert_eq!(rx.try_recv().map(|ev| get_n(&ev)), Some(2));
+        assert!(rx.try_recv().is_none());
+    }
+
+    #[test]
+    fn blocked_sends_are_counted_once() {
+        let multiplexer = Multiplexer::with_config(ChannelConfig::bounded(1, OverflowPolicy::Block));
+        let peer_id = Id::new();
+        let mut rx = multiplexer.get_channel(peer_id);
+        multiplexer.send(peer_id, ping(1));
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut send = std::pin::pin!(multiplexer.send_async(peer_id, ping(2)));
+        assert!(send.as_mut().poll(&mut cx).is_pending());
+        assert!(send.as_mut().poll(&mut cx).is_pending());
+
+        assert!(rx.try_recv().is_some());
+        assert!(send.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(multiplexer.with_channel(peer_id, |channel| channel.total_sent), 2);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+use bevy::reflect::Typed;
+use crate::prelude::*;
+
+/// A reflected struct that can be sent between peers as a `NetworkEvent` payload.
+pub trait NetworkPayload = Struct + Reflect + FromReflect + Typed + Clone + Send + Sync + 'static;
+
+/// A network event payload of type `T` received from another peer.
+#[derive(Event, Clone, Debug)]
+pub struct Received<T: NetworkPayload> {
+    pub sender_id: Id,
+    pub ev: T,
+}
+
+/// Writing this event sends `ev` to `recipient_id` through the session.
+#[derive(Event, Clone, Debug)]
+pub struct SendTo<T: NetworkPayload> {
+    pub recipient_id: Id,
+    pub ev: T,
+}
+
+impl<T: NetworkPayload> SendTo<T> {
+    pub fn new(recipient_id: Id, ev: T) -> Self {
+        Self {
+            recipient_id,
+            ev
+        }
+    }
+}
+
+/// Writing this event publishes `ev` to every other subscriber of `topic` through the session.
+#[derive(Event, Clone, Debug)]
+pub struct PublishTo<T: NetworkPayload> {
+    pub topic: String,
+    pub ev: T,
+}
+
+impl<T: NetworkPayload> PublishTo<T> {
+    pub fn new(topic: impl Into<String>, ev: T) -> Self {
+        Self {
+            topic: topic.into(),
+            ev
+        }
+    }
+}
+
+pub trait NetworkEventExt {
+    /// Decodes incoming `NetworkEvent`s carrying a `T` into `Received<T>` events,
+    /// sends `SendTo<T>` events to their recipients and publishes `PublishTo<T>` events to their topics.
+    fn add_network_event<T: NetworkPayload>(&mut self) -> &mut Self;
+}
+
+impl NetworkEventExt for App {
+    fn add_network_event<T: NetworkPayload>(&mut self) -> &mut Self {
+        self.add_event::<Received<T>>()
+            .add_event::<SendTo<T>>()
+            .add_event::<PublishTo<T>>()
+            .add_systems(Update, (
+                encode_network_events::<T>.before(NetworkSet::Relay).run_if(resource_exists::<Session>),
+                publish_network_events::<T>.before(NetworkSet::Relay).run_if(resource_exists::<Session>),
+                decode_network_events::<T>.after(NetworkSet::Relay),
+            ))
+    }
+}
+
+fn decode_network_events<T: NetworkPayload>(
+    mut network_evs: EventReader<NetworkEvent>,
+    mut received_evs: EventWriter<Received<T>>
+) {
+    for network_ev in network_evs.read() {
+        if !network_ev.is_ev::<T>() {
+            continue;
+        }
+
+        if let Some(ev) = network_ev.get_ev::<T>() {
+            received_evs.send(Received {
+                sender_id: network_ev.peer_id,
+                ev
+            });
+        } else {
+            warn!("Failed to decode network event {} from {}.", network_ev.get_ev_name(), network_ev.peer_id);
+        }
+    }
+}
+
+fn encode_network_events<T: NetworkPayload>(
+    session: Res<Session>,
+    mut send_evs: EventReader<SendTo<T>>,
+    mut peer_evs: EventWriter<PeerEvent>
+) {
+    for send_ev in send_evs.read() {
+        peer_evs.send(PeerEvent {
+            peer_id: Some(send_ev.recipient_id),
+            network_event: Some(NetworkEvent::new(session.get_id(), send_ev.ev.clone())),
+        });
+    }
+}
+
+fn publish_network_events<T: NetworkPayload>(
+    session: Res<Session>,
+    mut publish_evs: EventReader<PublishTo<T>>
+) {
+    for publish_ev in publish_evs.read() {
+        session.publish_ev(&publish_ev.topic, publish_ev.ev.clone());
+    }
+}
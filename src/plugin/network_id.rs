@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::reflect::{ReflectMut, ReflectRef};
+use crate::prelude::*;
+
+/// Identifies an entity across peers, since `Entity` values differ between worlds.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetworkId {
+    pub id: Id,
+    /// The peer the entity is mirrored from, or `None` if it was created locally.
+    pub owner: Option<Id>,
+}
+
+impl NetworkId {
+    pub fn new() -> Self {
+        Self {
+            id: Id::new(),
+            owner: None,
+        }
+    }
+
+    /// Identifies a local mirror of `owner`'s entity `id`.
+    pub fn remote(id: Id, owner: Id) -> Self {
+        Self {
+            id,
+            owner: Some(owner),
+        }
+    }
+}
+
+impl Default for NetworkId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+pub struct EntityIdMap {
+    to_entity: HashMap<Id, Entity>,
+    to_id: HashMap<Entity, Id>,
+}
+
+impl EntityIdMap {
+    pub fn get_entity(&self, id: &Id) -> Option<Entity> {
+        self.to_entity.get(id).copied()
+    }
+
+    pub fn get_id(&self, entity: &Entity) -> Option<Id> {
+        self.to_id.get(entity).copied()
+    }
+}
+
+/// `Id <-> Entity` maps for every entity with a `NetworkId`, kept per owning peer.
+/// Local entities are under `None`.
+#[derive(Resource, Default)]
+pub struct NetworkEntityMap {
+    owners: HashMap<Option<Id>, EntityIdMap>,
+}
+
+impl NetworkEntityMap {
+    pub fn get_owner_map(&self, owner: Option<Id>) -> Option<&EntityIdMap> {
+        self.owners.get(&owner)
+    }
+
+    /// The local entity for `id`, whichever peer owns it.
+    pub fn get_entity(&self, id: &Id) -> Option<Entity> {
+        self.owners.values().find_map(|map| map.get_entity(id))
+    }
+
+    pub fn get_id(&self, entity: &Entity) -> Option<Id> {
+        self.owners.values().find_map(|map| map.get_id(entity))
+    }
+
+    /// Records `entity` right away, for entities spawned this frame that later systems need to look up.
+    pub fn insert(&mut self, network_id: NetworkId, entity: Entity) {
+        let map = self.owners.entry(network_id.owner).or_default();
+        map.to_entity.insert(network_id.id, entity);
+        map.to_id.insert(entity, network_id.id);
+    }
+
+    pub fn remove_entity(&mut self, entity: &Entity) -> Option<Id> {
+        for map in self.owners.values_mut() {
+            if let Some(id) = map.to_id.remove(entity) {
+                map.to_entity.remove(&id);
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    /// The local entity for `id`, spawning an empty mirror owned by `owner` if there isn't one yet,
+    /// so references can resolve before the entity they point to has been replicated.
+    pub fn get_or_spawn(&mut self, commands: &mut Commands, id: Id, owner: Id) -> Entity {
+        if let Some(entity) = self.get_entity(&id) {
+            return entity;
+        }
+
+        let network_id = NetworkId::remote(id, owner);
+        let entity = commands.spawn(network_id).id();
+        self.insert(network_id, entity);
+        entity
+    }
+}
+
+/// An `Entity` inside a reflected value, by its reflect path, as the `Id` of the entity it points to.
+/// `None` if that entity has no `NetworkId` or is outside the receiver's interest, in which case the
+/// receiver gets `Entity::PLACEHOLDER`.
+#[derive(Reflect, Clone, Debug)]
+pub struct NetworkEntityRef {
+    pub path: String,
+    pub id: Option<Id>,
+}
+
+/// Finds every `Entity` in `value` and looks up its `NetworkId`. Entities the recipient can't see, for
+/// which `is_visible` returns false, are sent without an id so they aren't revealed to it.
+pub fn get_entity_refs(value: &dyn PartialReflect, entity_map: &NetworkEntityMap, is_visible: impl Fn(Entity) -> bool) -> Vec<NetworkEntityRef> {
+    let mut entities = Vec::new();
+    collect_entities(value, String::new(), &mut entities);
+    entities.into_iter()
+        .map(|(path, entity)| NetworkEntityRef {
+            id: entity_map.get_id(&entity).filter(|_| is_visible(entity)),
+            path,
+        })
+        .collect()
+}
+
+/// Points the entities in `value` at the local entities for `entity_refs`. With `spawn_missing`, which
+/// should only be set for peers entities are replicated from, mirrors owned by `owner` are spawned for
+/// ids that aren't known yet. Entities without a known ref are set to `Entity::PLACEHOLDER`, since the
+/// sender's `Entity` values mean nothing here.
+pub fn apply_entity_refs(value: &mut dyn PartialReflect, entity_refs: &[NetworkEntityRef], owner: Id, spawn_missing: bool, entity_map: &mut NetworkEntityMap, commands: &mut Commands) {
+    let refs: HashMap<&str, Option<Id>> = entity_refs.iter().map(|entity_ref| (entity_ref.path.as_str(), entity_ref.id)).collect();
+
+    map_entities(value, String::new(), &mut |path, entity| {
+        *entity = match refs.get(path).copied().flatten() {
+            Some(id) if spawn_missing => entity_map.get_or_spawn(commands, id, owner),
+            Some(id) => entity_map.get_entity(&id).unwrap_or(Entity::PLACEHOLDER),
+            None => Entity::PLACEHOLDER,
+        };
+    });
+}
+
+fn collect_entities(value: &dyn PartialReflect, path: String, entities: &mut Vec<(String, Entity)>) {
+    if let Some(entity) = value.try_downcast_ref::<Entity>() {
+        entities.push((path, *entity));
+        return;
+    }
+
+    match value.reflect_ref() {
+        ReflectRef::Struct(value) => {
+            for i in 0..value.field_len() {
+                collect_entities(value.field_at(i).unwrap(), format!("{}.{}", path, value.name_at(i).unwrap()), entities);
+            }
+        },
+        ReflectRef::TupleStruct(value) => {
+            for i in 0..value.field_len() {
+                collect_entities(value.field(i).unwrap(), format!("{}.{}", path, i), entities);
+            }
+        },
+        ReflectRef::Tuple(value) => {
+            for i in 0..value.field_len() {
+                collect_entities(value.field(i).unwrap(), format!("{}.{}", path, i), entities);
+            }
+        },
+        ReflectRef::List(value) => {
+            for i in 0..value.len() {
+                collect_entities(value.get(i).unwrap(), format!("{}[{}]", path, i), entities);
+            }
+        },
+        ReflectRef::Array(value) => {
+            for i in 0..value.len() {
+                collect_entities(value.get(i).unwrap(), format!("{}[{}]", path, i), entities);
+            }
+        },
+        ReflectRef::Enum(value) => {
+            for i in 0..value.field_len() {
+                let field_path = match value.name_at(i) {
+                    Some(name) => format!("{}.{}", path, name),
+                    None => format!("{}.{}", path, i),
+                };
+                collect_entities(value.field_at(i).unwrap(), field_path, entities);
+            }
+        },
+        // Map and set entries can't be addressed by a reflect path
+        _ => {},
+    }
+}
+
+/// Calls `f` with the path of every `Entity` in `value`, using the same paths as `collect_entities`.
+fn map_entities(value: &mut dyn PartialReflect, path: String, f: &mut impl FnMut(&str, &mut Entity)) {
+    if let Some(entity) = value.try_downcast_mut::<Entity>() {
+        f(&path, entity);
+        return;
+    }
+
+    match value.reflect_mut() {
+        ReflectMut::Struct(value) => {
+            for i in 0..value.field_len() {
+                let field_path = format!("{}.{}", path, value.name_at(i).unwrap());
+                map_entities(value.field_at_mut(i).unwrap(), field_path, f);
+            }
+        },
+        ReflectMut::TupleStruct(value) => {
+            for i in 0..value.field_len() {
+                map_entities(value.field_mut(i).unwrap(), format!("{}.{}", path, i), f);
+            }
+        },
+        ReflectMut::Tuple(value) => {
+            for i in 0..value.field_len() {
+                map_entities(value.field_mut(i).unwrap(), format!("{}.{}", path, i), f);
+            }
+        },
+        ReflectMut::List(value) => {
+            for i in 0..value.len() {
+                map_entities(value.get_mut(i).unwrap(), format!("{}[{}]", path, i), f);
+            }
+        },
+        ReflectMut::Array(value) => {
+            for i in 0..value.len() {
+                map_entities(value.get_mut(i).unwrap(), format!("{}[{}]", path, i), f);
+            }
+        },
+        ReflectMut::Enum(value) => {
+            for i in 0..value.field_len() {
+                let field_path = match value.name_at(i) {
+                    Some(name) => format!("{}.{}", path, name),
+                    None => format!("{}.{}", path, i),
+                };
+                map_entities(value.field_at_mut(i).unwrap(), field_path, f);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Adds entities that got a `NetworkId` to the map.
+pub fn index_network_ids(mut entity_map: ResMut<NetworkEntityMap>, added: Query<(Entity, &NetworkId), Added<NetworkId>>) {
+    for (entity, network_id) in added.iter() {
+        entity_map.insert(*network_id, entity);
+    }
+}
+
+/// Removes entities that lost their `NetworkId`, or were despawned, from the map.
+pub fn unindex_network_ids(mut entity_map: ResMut<NetworkEntityMap>, mut removed: RemovedComponents<NetworkId>) {
+    for entity in removed.read() {
+        entity_map.remove_entity(&entity);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy::platform::time::Instant;
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Sent when a peer gets its first receiver on the session's multiplexer.
+#[derive(Event, Debug, Clone)]
+pub struct PeerConnected {
+    pub peer_id: Id,
+}
+
+/// Sent when a peer loses its last receiver, including when its channel is parked for a resume.
+#[derive(Event, Debug, Clone)]
+pub struct PeerDisconnected {
+    pub peer_id: Id,
+}
+
+/// Sent when a connected peer hasn't sent anything for `PresenceConfig::idle_timeout`.
+#[derive(Event, Debug, Clone)]
+pub struct PeerIdle {
+    pub peer_id: Id,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct PresenceConfig {
+    /// How long a connected peer can go without sending an event before it's considered idle.
+    pub idle_timeout: Duration,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerPresence {
+    pub connected_at: Instant,
+    /// When the peer last sent an event, or connected if it hasn't sent any since.
+    pub last_activity: Instant,
+    pub is_idle: bool,
+}
+
+/// The peers currently connected to the session's multiplexer.
+#[derive(Resource, Default, Debug)]
+pub struct Presence {
+    peers: HashMap<Id, PeerPresence>,
+}
+
+impl Presence {
+    pub fn is_online(&self, peer_id: &Id) -> bool {
+        self.peers.contains_key(peer_id)
+    }
+
+    pub fn get(&self, peer_id: &Id) -> Option<&PeerPresence> {
+        self.peers.get(peer_id)
+    }
+
+    pub fn online_peers(&self) -> impl Iterator<Item = Id> + '_ {
+        self.peers.keys().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Id, &PeerPresence)> {
+        self.peers.iter()
+    }
+}
+
+/// Applies the multiplexer's presence changes to `Presence` and raises the matching events.
+pub fn update_presence(
+    session: Res<Session>,
+    config: Res<PresenceConfig>,
+    mut presence: ResMut<Presence>,
+    mut connected_evs: EventWriter<PeerConnected>,
+    mut disconnected_evs: EventWriter<PeerDisconnected>,
+    mut idle_evs: EventWriter<PeerIdle>,
+) {
+    let multiplexer = session.get_multiplexer();
+    let now = Instant::now();
+
+    for change in multiplexer.drain_presence_changes() {
+        match change {
+            PresenceChange::Connected(peer_id) => {
+                if presence.peers.contains_key(&peer_id) {
+                    continue;
+                }
+                presence.peers.insert(peer_id, PeerPresence {
+                    connected_at: now,
+                    last_activity: now,
+                    is_idle: false,
+                });
+                connected_evs.send(PeerConnected { peer_id });
+            },
+            PresenceChange::Disconnected(peer_id) => {
+                if presence.peers.remove(&peer_id).is_some() {
+                    disconnected_evs.send(PeerDisconnected { peer_id });
+                }
+            },
+        }
+    }
+
+    for (peer_id, peer) in presence.peers.iter_mut() {
+        if let Some(last_activity) = multiplexer.get_last_activity(*peer_id) {
+            peer.last_activity = peer.last_activity.max(last_activity);
+        }
+
+        let is_idle = now.duration_since(peer.last_activity) >= config.idle_timeout;
+        if is_idle && !peer.is_idle {
+            idle_evs.send(PeerIdle { peer_id: *peer_id });
+        }
+        peer.is_idle = is_idle;
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bevy::platform::time::Instant;
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// A token bucket refilled at `per_second` tokens a second, holding at most `burst`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst
+        }
+    }
+}
+
+/// What happens to an incoming event once its sender is over a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitAction {
+    /// Drops the event and raises an `EventRejected`.
+    #[default]
+    Drop,
+    /// Holds the sender's connection until the event fits within the limit.
+    Delay,
+    /// Drops the event and closes the sender's connection.
+    Disconnect,
+}
+
+/// Limits on events arriving from remote peers, applied per sender.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    /// Limit on all events from a peer.
+    pub per_peer: Option<RateLimit>,
+    /// Limits on events of a payload type from a peer, keyed by type path.
+    pub per_type: HashMap<String, RateLimit>,
+    pub action: RateLimitAction,
+}
+
+impl RateLimitConfig {
+    pub fn with_peer_limit(mut self, limit: RateLimit) -> Self {
+        self.per_peer = Some(limit);
+        self
+    }
+
+    pub fn with_type_limit<T>(mut self, limit: RateLimit) -> Self where T: TypePath {
+        self.per_type.insert(T::type_path().to_string(), limit);
+        self
+    }
+
+    pub fn with_action(mut self, action: RateLimitAction) -> Self {
+        self.action = action;
+        self
+    }
+}
+
+/// How many incoming events from a peer were let through or caught by a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitCounters {
+    pub allowed: u64,
+    pub dropped: u64,
+    pub delayed: u64,
+    pub disconnected: u64,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Refills the bucket and returns how long until it has a token, or zero if it has one now.
+    fn wait_time(&mut self, limit: &RateLimit, now: Instant) -> Duration {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else if limit.per_second > 0.0 {
+            Duration::from_secs_f64((1.0 - self.tokens) / limit.per_second)
+        } else {
+            Duration::MAX
+        }
+    }
+}
+
+/// Token buckets for each sender, and each sender and payload type, under a `RateLimitConfig`.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    peer_buckets: Mutex<HashMap<Id, TokenBucket>>,
+    type_buckets: Mutex<HashMap<(Id, String), TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            peer_buckets: Default::default(),
+            type_buckets: Default::default(),
+        }
+    }
+
+    pub fn get_action(&self) -> RateLimitAction {
+        self.config.action
+    }
+
+    /// Takes a token from every bucket `ev` counts against, or none if any is empty.
+    /// Returns how long until all of them have a token in that case.
+    pub fn try_acquire(&self, ev: &NetworkEvent) -> Result<(), Duration> {
+        let now = Instant::now();
+        let sender_id = ev.peer_id;
+
+        let mut peer_buckets = self.peer_buckets.lock().unwrap();
+        let mut type_buckets = self.type_buckets.lock().unwrap();
+
+        let mut peer_bucket = self.config.per_peer.as_ref().map(|limit| {
+            (peer_buckets.entry(sender_id).or_insert_with(|| TokenBucket::new(limit, now)), limit)
+        });
+        let mut type_bucket = ev.type_path()
+            .and_then(|type_path| self.config.per_type.get_key_value(type_path))
+            .map(|(type_path, limit)| {
+                (type_buckets.entry((sender_id, type_path.clone())).or_insert_with(|| TokenBucket::new(limit, now)), limit)
+            });
+
+        let wait = [peer_bucket.as_mut(), type_bucket.as_mut()].into_iter()
+            .flatten()
+            .map(|(bucket, limit)| bucket.wait_time(limit, now))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for (bucket, _) in [peer_bucket, type_bucket].into_iter().flatten() {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Drops `peer_id`'s buckets, so a disconnected peer doesn't keep them around.
+    pub fn forget_peer(&self, peer_id: Id) {
+        self.peer_buckets.lock().unwrap().remove(&peer_id);
+        self.type_buckets.lock().unwrap().retain(|(sender_id, _), _| *sender_id != peer_id);
+    }
+}
+
+/// Applies the `RateLimitConfig` resource, if the app inserted one, to the session's multiplexer.
+pub fn apply_rate_limits(session: Res<Session>, config: Option<Res<RateLimitConfig>>) {
+    if let Some(config) = config.filter(|config| config.is_changed()) {
+        session.get_multiplexer().set_rate_limits(Some(config.clone()));
+    }
+}
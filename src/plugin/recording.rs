@@ -0,0 +1,218 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+
+use anyhow::Result;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordDirection {
+    /// Received by the session and relayed to the app.
+    Incoming,
+    /// Sent by the session, whether through a `PeerEvent` or straight to its multiplexer.
+    Outgoing,
+}
+
+/// One line of a recording.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Frames since recording started.
+    pub frame: u64,
+    /// Seconds since recording started.
+    pub elapsed_secs: f64,
+    pub direction: RecordDirection,
+    /// The recipient of an outgoing event, or the session's own id for an incoming one.
+    pub recipient_id: Id,
+    pub network_event: NetworkEvent,
+}
+
+/// Writes every `NetworkEvent` the session sends or relays as a line of JSON, until it's removed.
+/// Responses are recorded too, although they're picked up by `Session::request` calls instead of relayed.
+#[derive(Resource)]
+pub struct NetworkRecorder {
+    writer: Box<dyn Write + Send + Sync>,
+    frame: u64,
+    elapsed_secs: f64,
+}
+
+impl NetworkRecorder {
+    pub fn new(writer: impl Write + Send + Sync + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+            frame: 0,
+            elapsed_secs: 0.0,
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn to_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Ok(Self::new(std::io::BufWriter::new(std::fs::File::create(path)?)))
+    }
+
+    pub(crate) fn write(&mut self, direction: RecordDirection, recipient_id: Id, network_event: &NetworkEvent) -> Result<()> {
+        let record = RecordedEvent {
+            frame: self.frame,
+            elapsed_secs: self.elapsed_secs,
+            direction,
+            recipient_id,
+            network_event: network_event.clone(),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl Drop for NetworkRecorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Feeds a recording's incoming events back through the session on the frames they were recorded on.
+/// Outgoing events are kept in the recording for reference but not replayed. Recorded responses go to
+/// the live `Session::request` made in place of the recorded request they answer, matched in order.
+#[derive(Resource)]
+pub struct NetworkReplay {
+    records: VecDeque<RecordedEvent>,
+    frame: u64,
+    /// Recorded requests no live request has been matched to yet, oldest first.
+    unmatched_requests: VecDeque<RecordedEvent>,
+    /// Correlation ids of recorded requests, mapped to those of the live requests matched to them.
+    correlations: HashMap<Id, Id>,
+    /// Recorded responses whose request hasn't been made live yet.
+    pending_responses: Vec<NetworkEvent>,
+}
+
+impl NetworkReplay {
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        let mut records = VecDeque::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push_back(serde_json::from_str(&line)?);
+        }
+
+        let unmatched_requests = records.iter()
+            .filter(|record| record.direction == RecordDirection::Outgoing
+                && matches!(record.network_event.correlation, Some(Correlation::Request(_))))
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            records,
+            frame: 0,
+            unmatched_requests,
+            correlations: HashMap::new(),
+            pending_responses: Vec::new(),
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Self::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Whether every recorded event has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.records.iter().all(|record| record.direction == RecordDirection::Outgoing) && self.pending_responses.is_empty()
+    }
+
+    /// Matches a live request to the oldest unmatched recorded one with the same recipient and type.
+    fn match_request(&mut self, recipient_id: Id, network_event: &NetworkEvent) {
+        let Some(Correlation::Request(live_id)) = network_event.correlation else {
+            return;
+        };
+        let Some(index) = self.unmatched_requests.iter().position(|record| {
+            record.recipient_id == recipient_id && record.network_event.type_path() == network_event.type_path()
+        }) else {
+            return;
+        };
+
+        if let Some(Correlation::Request(recorded_id)) = self.unmatched_requests.remove(index).and_then(|record| record.network_event.correlation) {
+            self.correlations.insert(recorded_id, live_id);
+        }
+    }
+
+    /// Takes the pending responses whose request was matched, correlated with the live request.
+    fn take_answered_responses(&mut self) -> Vec<NetworkEvent> {
+        let correlations = &self.correlations;
+        let (answered, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_responses).into_iter()
+            .partition(|response| matches!(response.correlation, Some(Correlation::Response(id)) if correlations.contains_key(&id)));
+        self.pending_responses = pending;
+
+        answered.into_iter().map(|mut response| {
+            if let Some(Correlation::Response(id)) = response.correlation {
+                response.correlation = Some(Correlation::Response(correlations[&id]));
+            }
+            response
+        }).collect()
+    }
+}
+
+/// Taps the session's multiplexer for the events the session sends while a `NetworkRecorder` or `NetworkReplay` exists.
+pub fn apply_recorder_tap(session: Res<Session>, recorder: Option<Res<NetworkRecorder>>, replay: Option<Res<NetworkReplay>>, mut tapped: Local<bool>) {
+    let recording = recorder.is_some() || replay.is_some();
+    if recording != *tapped {
+        session.get_multiplexer().tap_sent(recording.then(|| session.get_id()));
+        *tapped = recording;
+    }
+}
+
+/// Records the events the session sent since the last frame, including ones sent outside of `PeerEvent`s.
+pub fn record_outgoing_events(session: Res<Session>, mut recorder: ResMut<NetworkRecorder>) {
+    for (recipient_id, network_event) in session.get_multiplexer().drain_sent_evs() {
+        if let Err(err) = recorder.write(RecordDirection::Outgoing, recipient_id, &network_event) {
+            warn!("Failed to record outgoing network event: {}", err);
+        }
+    }
+}
+
+/// Records the events relayed this frame, then moves on to the next frame.
+pub fn record_incoming_events(
+    session: Res<Session>,
+    time: Res<Time<Real>>,
+    mut recorder: ResMut<NetworkRecorder>,
+    mut network_evs: EventReader<NetworkEvent>,
+) {
+    for ev in network_evs.read() {
+        if let Err(err) = recorder.write(RecordDirection::Incoming, session.get_id(), ev) {
+            warn!("Failed to record incoming network event: {}", err);
+        }
+    }
+
+    recorder.frame += 1;
+    recorder.elapsed_secs += time.delta_secs_f64();
+}
+
+/// Sends this frame's recorded incoming events to the session, so `relay_network_events` picks them up this frame.
+/// Recorded responses wait for the live request they answer, and go to it through its correlation id.
+pub fn replay_network_events(session: Res<Session>, mut replay: ResMut<NetworkReplay>, mut recorder: Option<ResMut<NetworkRecorder>>) {
+    let multiplexer = session.get_multiplexer();
+    // Drained here to match requests, so they're recorded here too
+    for (recipient_id, network_event) in multiplexer.drain_sent_evs() {
+        replay.match_request(recipient_id, &network_event);
+        if let Some(recorder) = recorder.as_mut()
+            && let Err(err) = recorder.write(RecordDirection::Outgoing, recipient_id, &network_event) {
+            warn!("Failed to record outgoing network event: {}", err);
+        }
+    }
+
+    let frame = replay.frame;
+    while replay.records.front().is_some_and(|record| record.frame <= frame) {
+        let record = replay.records.pop_front().unwrap();
+        match record.direction {
+            RecordDirection::Incoming if record.network_event.is_response() => replay.pending_responses.push(record.network_event),
+            RecordDirection::Incoming => multiplexer.send(session.get_id(), record.network_event),
+            RecordDirection::Outgoing => {},
+        }
+    }
+
+    for response in replay.take_answered_responses() {
+        multiplexer.send(session.get_id(), response);
+    }
+    replay.frame += 1;
+}
@@ -0,0 +1,685 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+use bevy::reflect::TypeRegistry;
+use crate::prelude::*;
+
+/// How many unacknowledged states are kept per peer and component, and how many received states
+/// are kept per component to apply patches to.
+const MAX_PENDING_REVISIONS: usize = 16;
+
+/// Marks a server entity whose replicated components are sent to the subscribed peers its interest rules
+/// let see it, see `PeerInterest`.
+/// Mirrors of it on other peers get its `NetworkId`, with the server as the owner, and no `Replicated`,
+/// so they're never replicated back.
+#[derive(Component, Debug, Clone, Default)]
+#[require(NetworkId)]
+pub struct Replicated;
+
+/// Set alongside `T` whenever a replicated `T` received from `peer_id` is applied. While `T` hasn't
+/// changed since, its current value is that peer's, so it isn't sent back to it.
+#[derive(Component, Debug)]
+pub struct ReceivedFrom<T: Send + Sync + 'static> {
+    pub peer_id: Id,
+    marker: PhantomData<T>,
+}
+
+impl<T: Send + Sync + 'static> ReceivedFrom<T> {
+    pub fn new(peer_id: Id) -> Self {
+        Self {
+            peer_id,
+            marker: PhantomData,
+        }
+    }
+
+    /// Whether `component` still holds the value received from `peer_id`.
+    fn is_echo(received_from: Option<&Ref<Self>>, component: &Ref<T>, peer_id: Id) -> bool {
+        // Both are inserted by the same command, so their change ticks match until `T` changes again
+        received_from.is_some_and(|received_from| received_from.peer_id == peer_id && received_from.last_changed() == component.last_changed())
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct ReplicationConfig {
+    /// Topic replicated changes are published to.
+    pub topic: String,
+    /// Subscribes peers to `topic` as soon as they connect.
+    pub subscribe_on_connect: bool,
+    /// Once a peer has acknowledged a component, sends only the fields that changed since.
+    pub delta_compression: bool,
+    /// Sends replicated entities without interest rules to every subscriber of `topic`.
+    /// Off by default, so nothing is sent to a peer unless a rule like `InRoom` lets it see the entity.
+    pub open_visibility: bool,
+    /// Peers whose replicated entities are mirrored here. Other peers can only change entities they have
+    /// `Authority` over, and can't create any. Defaults to the server, at `Id::nil()`.
+    pub sources: HashSet<Id>,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            topic: "replication".to_string(),
+            subscribe_on_connect: true,
+            delta_compression: true,
+            open_visibility: false,
+            sources: HashSet::from([Id::nil()]),
+        }
+    }
+}
+
+/// Sets a component on a replicated entity. `data` is the component encoded with postcard, and
+/// `entity_refs` are the entities it references, which the receiver remaps to its own.
+#[derive(Reflect, Clone, Debug)]
+pub struct ReplicateComponent {
+    pub entity_id: Id,
+    pub component_type: String,
+    pub revision: u64,
+    pub data: Vec<u8>,
+    pub entity_refs: Vec<NetworkEntityRef>,
+}
+
+/// Sets the changed fields of a replicated component, relative to `base_revision`, the last revision
+/// of it the receiver acknowledged.
+#[derive(Reflect, Clone, Debug)]
+pub struct ReplicatePatch {
+    pub entity_id: Id,
+    pub component_type: String,
+    pub revision: u64,
+    pub base_revision: u64,
+    pub fields: Vec<FieldPatch>,
+    pub entity_refs: Vec<NetworkEntityRef>,
+}
+
+/// Sent back for every `ReplicateComponent` and `ReplicatePatch` applied, so later changes can be patches.
+#[derive(Reflect, Clone, Debug)]
+pub struct AcknowledgeReplication {
+    pub entity_id: Id,
+    pub component_type: String,
+    pub revision: u64,
+}
+
+/// Sent back instead of an acknowledgement when a patch's base revision is unknown,
+/// so the sender starts over with a `ReplicateComponent`.
+#[derive(Reflect, Clone, Debug)]
+pub struct ResyncReplication {
+    pub entity_id: Id,
+    pub component_type: String,
+}
+
+#[derive(Reflect, Clone, Debug)]
+pub struct RemoveReplicatedComponent {
+    pub entity_id: Id,
+    pub component_type: String,
+}
+
+/// Sent when a replicated entity enters a peer's interest, before any of its components.
+#[derive(Reflect, Clone, Debug)]
+pub struct SpawnReplicated {
+    pub entity_id: Id,
+}
+
+/// Sent when a replicated entity is despawned or leaves a peer's interest.
+#[derive(Reflect, Clone, Debug)]
+pub struct DespawnReplicated {
+    pub entity_id: Id,
+}
+
+#[derive(Default)]
+struct Baseline {
+    acked: Option<(u64, Box<dyn PartialReflect>)>,
+    pending: VecDeque<(u64, Box<dyn PartialReflect>)>,
+}
+
+/// States of replicated components sent to each peer, by peer, entity and component type.
+#[derive(Resource, Default)]
+pub struct ReplicationBaselines {
+    next_revision: u64,
+    baselines: HashMap<(Id, Id, String), Baseline>,
+}
+
+impl ReplicationBaselines {
+    fn forget(&mut self, peer_id: Id, entity_id: Id, component_type: &str) {
+        self.baselines.remove(&(peer_id, entity_id, component_type.to_string()));
+    }
+
+    fn forget_entity(&mut self, peer_id: Id, entity_id: Id) {
+        self.baselines.retain(|(baseline_peer_id, baseline_entity_id, _), _| *baseline_peer_id != peer_id || *baseline_entity_id != entity_id);
+    }
+
+    fn forget_component(&mut self, entity_id: Id, component_type: &str) {
+        self.baselines.retain(|(_, baseline_entity_id, baseline_type), _| *baseline_entity_id != entity_id || baseline_type != component_type);
+    }
+}
+
+/// Recently received states of replicated components, by sender, entity and component type, that patches apply to.
+/// Kept per sender since each numbers its revisions separately.
+#[derive(Resource, Default)]
+pub struct ReplicationHistory {
+    states: HashMap<(Id, Id, String), VecDeque<(u64, Box<dyn PartialReflect>)>>,
+    /// Components a `ResyncReplication` was sent for, until the sender sends them whole.
+    resyncing: HashSet<(Id, Id, String)>,
+}
+
+impl ReplicationHistory {
+    /// Whether a newer revision than `revision` from `sender_id` has already been applied.
+    fn is_stale(&self, sender_id: Id, entity_id: Id, component_type: &str, revision: u64) -> bool {
+        self.states.get(&(sender_id, entity_id, component_type.to_string()))
+            .and_then(|states| states.back())
+            .is_some_and(|(latest, _)| *latest >= revision)
+    }
+
+    fn get_state<T: FluxRecord>(&self, sender_id: Id, entity_id: Id, component_type: &str, revision: u64) -> Option<T> {
+        self.states.get(&(sender_id, entity_id, component_type.to_string()))?
+            .iter()
+            .find(|(state_revision, _)| *state_revision == revision)
+            .and_then(|(_, state)| state.try_downcast_ref::<T>().cloned())
+    }
+
+    /// Records `state`, dropping states older than `base_revision`, which the sender won't patch against anymore.
+    /// The base itself is kept, since later patches apply to it until the sender hears of a newer state.
+    fn push<T: FluxRecord>(&mut self, sender_id: Id, entity_id: Id, component_type: &str, revision: u64, base_revision: Option<u64>, state: T) {
+        let key = (sender_id, entity_id, component_type.to_string());
+        if base_revision.is_none() {
+            self.resyncing.remove(&key);
+        }
+
+        let states = self.states.entry(key).or_default();
+        if let Some(base_revision) = base_revision {
+            states.retain(|(state_revision, _)| *state_revision >= base_revision);
+        }
+        states.push_back((revision, Box::new(state)));
+        if states.len() > MAX_PENDING_REVISIONS {
+            let oldest = if base_revision.is_some() { 1 } else { 0 };
+            states.remove(oldest);
+        }
+    }
+
+    /// Whether a `ResyncReplication` should be sent, which is only once until the component arrives whole.
+    fn start_resync(&mut self, sender_id: Id, entity_id: Id, component_type: &str) -> bool {
+        self.resyncing.insert((sender_id, entity_id, component_type.to_string()))
+    }
+
+    fn forget_entity(&mut self, entity_id: Id) {
+        self.states.retain(|(_, state_entity_id, _), _| *state_entity_id != entity_id);
+        self.resyncing.retain(|(_, state_entity_id, _)| *state_entity_id != entity_id);
+    }
+
+    fn forget_component(&mut self, entity_id: Id, component_type: &str) {
+        self.states.retain(|(_, state_entity_id, state_type), _| *state_entity_id != entity_id || state_type != component_type);
+        self.resyncing.retain(|(_, state_entity_id, state_type)| *state_entity_id != entity_id || state_type != component_type);
+    }
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReplicationSet {
+    /// Applies changes received from other peers to local mirrors.
+    Receive,
+    /// Sends changes to local replicated entities.
+    Send,
+}
+
+pub trait ReplicationAppExt {
+    /// Sends changes to `T` on `Replicated` entities to the subscribers of the replication topic whose
+    /// interest they're in (see `PeerInterest`), and applies changes to `T` received from other peers to
+    /// their local mirrors, remapping any `Entity` fields in `T` to the matching local entities.
+    /// Peers with `Authority` over a mirror send their changes to `T` back, and changes from peers
+    /// without it are rejected with `EventRejected`.
+    fn replicate<T: FluxRecord>(&mut self) -> &mut Self;
+}
+
+impl ReplicationAppExt for App {
+    fn replicate<T: FluxRecord>(&mut self) -> &mut Self {
+        if !self.world().contains_resource::<ReplicationConfig>() {
+            self.init_resource::<ReplicationConfig>()
+                .init_resource::<ReplicationBaselines>()
+                .init_resource::<ReplicationHistory>()
+                .init_resource::<PeerInterest>()
+                .init_resource::<AuthorityConfig>()
+                .add_event::<AuthorityRequested>()
+                .add_network_event::<RequestAuthority>()
+                .add_network_event::<ReleaseAuthority>()
+                .add_network_event::<TransferAuthority>()
+                .add_network_event::<AuthorityChanged>()
+                .add_network_event::<SpawnReplicated>()
+                .add_network_event::<ReplicateComponent>()
+                .add_network_event::<ReplicatePatch>()
+                .add_network_event::<AcknowledgeReplication>()
+                .add_network_event::<ResyncReplication>()
+                .add_network_event::<RemoveReplicatedComponent>()
+                .add_network_event::<DespawnReplicated>()
+                .configure_sets(PostUpdate, (ReplicationSet::Receive, ReplicationSet::Send).chain().run_if(resource_exists::<Session>))
+                .configure_sets(PostUpdate, (InterestSet::Evaluate, InterestSet::Filter).chain()
+                    .after(receive_replication_acks)
+                    .after(TransformSystem::TransformPropagate)
+                    .in_set(ReplicationSet::Send))
+                .add_systems(PostUpdate, (
+                    (spawn_mirrors, despawn_mirrors, receive_authority_requests).in_set(ReplicationSet::Receive),
+                    apply_authority_changes.after(spawn_mirrors).in_set(ReplicationSet::Receive),
+                    (subscribe_replication_peers, receive_replication_acks).chain().in_set(ReplicationSet::Send),
+                    evaluate_interest.in_set(InterestSet::Evaluate),
+                    (send_interest_changes, send_authority_changes).chain().after(InterestSet::Filter).in_set(ReplicationSet::Send),
+                ));
+        }
+
+        self.register_type::<T>()
+            .add_systems(PostUpdate, (
+                (apply_replicated_components::<T>, apply_replicated_patches::<T>, remove_replicated_components::<T>).after(spawn_mirrors).in_set(ReplicationSet::Receive),
+                (send_component_changes::<T>, send_component_removals::<T>, send_full_state::<T>, send_authorized_changes::<T>, resync_components::<T>).after(send_interest_changes).in_set(ReplicationSet::Send),
+            ))
+    }
+}
+
+/// Spawns mirrors for entities replicated from `ReplicationConfig::sources`.
+fn spawn_mirrors(
+    mut commands: Commands,
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    mut entity_map: ResMut<NetworkEntityMap>,
+    mut spawn_evs: EventReader<Received<SpawnReplicated>>,
+    mut replicate_evs: EventReader<Received<ReplicateComponent>>,
+    mut rejected_evs: EventWriter<EventRejected>,
+) {
+    for Received { sender_id, ev } in spawn_evs.read() {
+        if config.sources.contains(sender_id) {
+            entity_map.get_or_spawn(&mut commands, ev.entity_id, *sender_id);
+        } else {
+            reject_change::<SpawnReplicated>(&session, &mut rejected_evs, *sender_id);
+        }
+    }
+    // Components from other peers are rejected by `apply_replicated_components` unless they have authority
+    for Received { sender_id, ev } in replicate_evs.read().filter(|received| config.sources.contains(&received.sender_id)) {
+        entity_map.get_or_spawn(&mut commands, ev.entity_id, *sender_id);
+    }
+}
+
+fn despawn_mirrors(
+    mut commands: Commands,
+    entity_map: Res<NetworkEntityMap>,
+    mut history: ResMut<ReplicationHistory>,
+    mut despawn_evs: EventReader<Received<DespawnReplicated>>,
+) {
+    for Received { sender_id, ev } in despawn_evs.read() {
+        // Only the owner can despawn its entities
+        let entity = entity_map.get_owner_map(Some(*sender_id)).and_then(|map| map.get_entity(&ev.entity_id));
+        if let Some(entity) = entity {
+            history.forget_entity(ev.entity_id);
+            commands.entity(entity).try_despawn();
+        }
+    }
+}
+
+/// The local entity for `entity_id` if `sender_id` may change it: a mirror of one of the entities of
+/// a peer this session replicates from, or a local replicated entity the sender has `Authority` over.
+fn get_authorized_entity(config: &ReplicationConfig, entity_map: &NetworkEntityMap, authorities: &Query<&Authority, With<Replicated>>, sender_id: Id, entity_id: Id) -> Option<Entity> {
+    let mirror = entity_map.get_owner_map(Some(sender_id))
+        .filter(|_| config.sources.contains(&sender_id))
+        .and_then(|map| map.get_entity(&entity_id));
+    if mirror.is_some() {
+        return mirror;
+    }
+    entity_map.get_owner_map(None)
+        .and_then(|map| map.get_entity(&entity_id))
+        .filter(|entity| authorities.get(*entity).is_ok_and(|authority| *authority == Authority::Peer(sender_id)))
+}
+
+fn reject_change<T: TypePath>(session: &Session, rejected_evs: &mut EventWriter<EventRejected>, sender_id: Id) {
+    rejected_evs.send(EventRejected {
+        sender_id,
+        recipient_id: session.get_id(),
+        type_path: Some(T::type_path().to_string()),
+        reason: RejectReason::NoAuthority,
+    });
+}
+
+fn acknowledge(session: &Session, sender_id: Id, entity_id: Id, component_type: &str, revision: u64) {
+    session.get_multiplexer().send(sender_id, NetworkEvent::new(session.get_id(), AcknowledgeReplication {
+        entity_id,
+        component_type: component_type.to_string(),
+        revision,
+    }));
+}
+
+fn apply_replicated_components<T: FluxRecord>(
+    mut commands: Commands,
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    mut entity_map: ResMut<NetworkEntityMap>,
+    mut history: ResMut<ReplicationHistory>,
+    authorities: Query<&Authority, With<Replicated>>,
+    mut replicate_evs: EventReader<Received<ReplicateComponent>>,
+    mut rejected_evs: EventWriter<EventRejected>,
+) {
+    for Received { sender_id, ev } in replicate_evs.read() {
+        if ev.component_type != T::type_path() || history.is_stale(*sender_id, ev.entity_id, &ev.component_type, ev.revision) {
+            continue;
+        }
+        let Some(entity) = get_authorized_entity(&config, &entity_map, &authorities, *sender_id, ev.entity_id) else {
+            reject_change::<ReplicateComponent>(&session, &mut rejected_evs, *sender_id);
+            continue;
+        };
+
+        match postcard::from_bytes::<T>(&ev.data) {
+            Ok(mut component) => {
+                apply_entity_refs(&mut component, &ev.entity_refs, *sender_id, config.sources.contains(sender_id), &mut entity_map, &mut commands);
+                history.push(*sender_id, ev.entity_id, &ev.component_type, ev.revision, None, component.clone());
+                commands.entity(entity).insert((component, ReceivedFrom::<T>::new(*sender_id)));
+                acknowledge(&session, *sender_id, ev.entity_id, &ev.component_type, ev.revision);
+            },
+            Err(err) => warn!("Failed to decode replicated {} from {}: {}", T::short_type_path(), sender_id, err),
+        }
+    }
+}
+
+fn apply_replicated_patches<T: FluxRecord>(
+    mut commands: Commands,
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    type_registry: Res<AppTypeRegistry>,
+    mut entity_map: ResMut<NetworkEntityMap>,
+    mut history: ResMut<ReplicationHistory>,
+    authorities: Query<&Authority, With<Replicated>>,
+    mut patch_evs: EventReader<Received<ReplicatePatch>>,
+    mut rejected_evs: EventWriter<EventRejected>,
+) {
+    let type_registry = type_registry.read();
+    for Received { sender_id, ev } in patch_evs.read() {
+        if ev.component_type != T::type_path() || history.is_stale(*sender_id, ev.entity_id, &ev.component_type, ev.revision) {
+            continue;
+        }
+        let Some(entity) = get_authorized_entity(&config, &entity_map, &authorities, *sender_id, ev.entity_id) else {
+            reject_change::<ReplicatePatch>(&session, &mut rejected_evs, *sender_id);
+            continue;
+        };
+        let Some(mut component) = history.get_state::<T>(*sender_id, ev.entity_id, &ev.component_type, ev.base_revision) else {
+            if history.start_resync(*sender_id, ev.entity_id, &ev.component_type) {
+                warn!("Base revision {} of replicated {} from {} is unknown, asking for it whole", ev.base_revision, T::short_type_path(), sender_id);
+                session.get_multiplexer().send(*sender_id, NetworkEvent::new(session.get_id(), ResyncReplication {
+                    entity_id: ev.entity_id,
+                    component_type: ev.component_type.clone(),
+                }));
+            }
+            continue;
+        };
+
+        match apply_patch(&mut component, &ev.fields, &type_registry) {
+            Ok(()) => {
+                apply_entity_refs(&mut component, &ev.entity_refs, *sender_id, config.sources.contains(sender_id), &mut entity_map, &mut commands);
+                history.push(*sender_id, ev.entity_id, &ev.component_type, ev.revision, Some(ev.base_revision), component.clone());
+                commands.entity(entity).insert((component, ReceivedFrom::<T>::new(*sender_id)));
+                acknowledge(&session, *sender_id, ev.entity_id, &ev.component_type, ev.revision);
+            },
+            Err(err) => warn!("Failed to patch replicated {} from {}: {}", T::short_type_path(), sender_id, err),
+        }
+    }
+}
+
+fn remove_replicated_components<T: FluxRecord>(
+    mut commands: Commands,
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    entity_map: Res<NetworkEntityMap>,
+    mut history: ResMut<ReplicationHistory>,
+    authorities: Query<&Authority, With<Replicated>>,
+    mut remove_evs: EventReader<Received<RemoveReplicatedComponent>>,
+    mut rejected_evs: EventWriter<EventRejected>,
+) {
+    for Received { sender_id, ev } in remove_evs.read() {
+        if ev.component_type != T::type_path() {
+            continue;
+        }
+        let Some(entity) = get_authorized_entity(&config, &entity_map, &authorities, *sender_id, ev.entity_id) else {
+            reject_change::<RemoveReplicatedComponent>(&session, &mut rejected_evs, *sender_id);
+            continue;
+        };
+        history.forget_component(ev.entity_id, &ev.component_type);
+        commands.entity(entity).remove::<T>();
+    }
+}
+
+fn subscribe_replication_peers(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    mut connected_evs: EventReader<PeerConnected>,
+) {
+    for ev in connected_evs.read() {
+        if config.subscribe_on_connect && ev.peer_id != session.get_id() {
+            session.get_multiplexer().subscribe(&config.topic, ev.peer_id);
+        }
+    }
+}
+
+fn receive_replication_acks(
+    mut baselines: ResMut<ReplicationBaselines>,
+    mut ack_evs: EventReader<Received<AcknowledgeReplication>>,
+) {
+    for Received { sender_id, ev } in ack_evs.read() {
+        let Some(baseline) = baselines.baselines.get_mut(&(*sender_id, ev.entity_id, ev.component_type.clone())) else {
+            continue;
+        };
+        // Older pending states will never be acknowledged once a newer one is
+        if let Some(index) = baseline.pending.iter().position(|(revision, _)| *revision == ev.revision) {
+            baseline.acked = baseline.pending.drain(..=index).last();
+        }
+    }
+}
+
+/// Spawns entities that entered a peer's interest on it, and despawns those that left it or were despawned.
+/// Peers start over from full state when an entity comes back into their interest.
+fn send_interest_changes(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    interest: Res<PeerInterest>,
+    entity_map: Res<NetworkEntityMap>,
+    mut baselines: ResMut<ReplicationBaselines>,
+) {
+    let multiplexer = session.get_multiplexer();
+    // Runs before `unindex_network_ids`, so despawned entities are still in the map
+    let Some(local_ids) = entity_map.get_owner_map(None) else {
+        return;
+    };
+
+    let subscribers = multiplexer.get_subscribers(&config.topic);
+    for (peer_id, left) in interest.get_left() {
+        for entity_id in left.iter().filter_map(|entity| local_ids.get_id(entity)) {
+            baselines.forget_entity(peer_id, entity_id);
+            // Peers that unsubscribed don't get updates anymore either way
+            if subscribers.contains(&peer_id) {
+                multiplexer.send(peer_id, NetworkEvent::new(session.get_id(), DespawnReplicated { entity_id }));
+            }
+        }
+    }
+
+    for peer_id in subscribers {
+        for entity_id in interest.get_entered(peer_id).iter().filter_map(|entity| local_ids.get_id(entity)) {
+            multiplexer.send(peer_id, NetworkEvent::new(session.get_id(), SpawnReplicated { entity_id }));
+        }
+    }
+}
+
+/// Sends `component` to `peer_id`, as a patch against the last state it acknowledged if there is one.
+fn send_component<T: FluxRecord>(
+    session: &Session,
+    baselines: &mut ReplicationBaselines,
+    type_registry: &TypeRegistry,
+    delta_compression: bool,
+    peer_id: Id,
+    entity_id: Id,
+    component: &T,
+    entity_refs: &[NetworkEntityRef],
+) {
+    baselines.next_revision += 1;
+    let revision = baselines.next_revision;
+    let baseline = baselines.baselines.entry((peer_id, entity_id, T::type_path().to_string())).or_default();
+
+    let patch = baseline.acked.as_ref().filter(|_| delta_compression).and_then(|(base_revision, base_state)| {
+        match encode_patch(component, diff_fields(&**base_state, component), type_registry) {
+            Ok(fields) => Some(ReplicatePatch {
+                entity_id,
+                component_type: T::type_path().to_string(),
+                revision,
+                base_revision: *base_revision,
+                fields,
+                entity_refs: entity_refs.to_vec(),
+            }),
+            Err(err) => {
+                warn!("Failed to diff replicated {}, sending it whole: {}", T::short_type_path(), err);
+                None
+            }
+        }
+    });
+
+    let network_event = match patch {
+        Some(patch) => NetworkEvent::new(session.get_id(), patch),
+        None => match postcard::to_allocvec(component) {
+            Ok(data) => NetworkEvent::new(session.get_id(), ReplicateComponent {
+                entity_id,
+                component_type: T::type_path().to_string(),
+                revision,
+                data,
+                entity_refs: entity_refs.to_vec(),
+            }),
+            Err(err) => {
+                warn!("Failed to encode replicated {}: {}", T::short_type_path(), err);
+                return;
+            }
+        },
+    };
+
+    session.get_multiplexer().send(peer_id, network_event);
+    baseline.pending.push_back((revision, Box::new(component.clone())));
+    if baseline.pending.len() > MAX_PENDING_REVISIONS {
+        baseline.pending.pop_front();
+    }
+}
+
+fn send_component_changes<T: FluxRecord>(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    type_registry: Res<AppTypeRegistry>,
+    entity_map: Res<NetworkEntityMap>,
+    interest: Res<PeerInterest>,
+    mut baselines: ResMut<ReplicationBaselines>,
+    changed: Query<(Entity, &NetworkId, Ref<T>, Option<Ref<ReceivedFrom<T>>>), (With<Replicated>, Changed<T>)>,
+) {
+    let type_registry = type_registry.read();
+    for (entity, network_id, component, received_from) in changed.iter() {
+        for peer_id in interest.get_peers(entity) {
+            // Don't send a peer with authority its own change back
+            if ReceivedFrom::is_echo(received_from.as_ref(), &component, peer_id) {
+                continue;
+            }
+            // `send_full_state` sends it whole to peers it just entered the interest of
+            if interest.has_entered(peer_id, entity) {
+                continue;
+            }
+            let entity_refs = get_entity_refs(&*component, &entity_map, |entity| interest.is_visible(peer_id, entity));
+            send_component(&session, &mut baselines, &type_registry, config.delta_compression, peer_id, network_id.id, &*component, &entity_refs);
+        }
+    }
+}
+
+fn send_component_removals<T: FluxRecord>(
+    session: Res<Session>,
+    interest: Res<PeerInterest>,
+    mut baselines: ResMut<ReplicationBaselines>,
+    query: Query<&NetworkId, With<Replicated>>,
+    mut removed: RemovedComponents<T>,
+) {
+    for entity in removed.read() {
+        // Despawned entities are covered by `DespawnReplicated`
+        if let Ok(network_id) = query.get(entity) {
+            baselines.forget_component(network_id.id, T::type_path());
+            for peer_id in interest.get_peers(entity) {
+                session.get_multiplexer().send(peer_id, NetworkEvent::new(session.get_id(), RemoveReplicatedComponent {
+                    entity_id: network_id.id,
+                    component_type: T::type_path().to_string(),
+                }));
+            }
+        }
+    }
+}
+
+/// Brings peers up to date with every replicated `T` that just entered their interest.
+fn send_full_state<T: FluxRecord>(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    type_registry: Res<AppTypeRegistry>,
+    interest: Res<PeerInterest>,
+    entity_map: Res<NetworkEntityMap>,
+    mut baselines: ResMut<ReplicationBaselines>,
+    query: Query<(&NetworkId, &T), With<Replicated>>,
+) {
+    let type_registry = type_registry.read();
+    for peer_id in session.get_multiplexer().get_subscribers(&config.topic) {
+        // `send_interest_changes` forgot these entities when they last left, so they're sent whole
+        for (network_id, component) in interest.get_entered(peer_id).into_iter().filter_map(|entity| query.get(entity).ok()) {
+            let entity_refs = get_entity_refs(component, &entity_map, |entity| interest.is_visible(peer_id, entity));
+            send_component(&session, &mut baselines, &type_registry, config.delta_compression, peer_id, network_id.id, component, &entity_refs);
+        }
+    }
+}
+
+/// Sends changes to `T` on mirrors this peer has `Authority` over to the peer they're replicated from.
+fn send_authorized_changes<T: FluxRecord>(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    type_registry: Res<AppTypeRegistry>,
+    entity_map: Res<NetworkEntityMap>,
+    mut baselines: ResMut<ReplicationBaselines>,
+    changed: Query<(&NetworkId, &Authority, Ref<T>, Option<Ref<ReceivedFrom<T>>>), (Without<Replicated>, Changed<T>)>,
+) {
+    let type_registry = type_registry.read();
+    for (network_id, authority, component, received_from) in changed.iter() {
+        let Some(owner_id) = network_id.owner.filter(|_| *authority == Authority::Peer(session.get_id())) else {
+            continue;
+        };
+        // Skip changes that only applied the owner's state
+        if ReceivedFrom::is_echo(received_from.as_ref(), &component, owner_id) {
+            continue;
+        }
+
+        // The owner replicated these entities here, or they're this peer's own
+        let entity_refs = get_entity_refs(&*component, &entity_map, |_| true);
+        send_component(&session, &mut baselines, &type_registry, config.delta_compression, owner_id, network_id.id, &*component, &entity_refs);
+    }
+}
+
+/// Sends `T` whole to peers that lost track of it. Only peers that can see the entity, or the peer
+/// a mirror this peer has authority over is replicated from, are answered.
+fn resync_components<T: FluxRecord>(
+    session: Res<Session>,
+    config: Res<ReplicationConfig>,
+    type_registry: Res<AppTypeRegistry>,
+    interest: Res<PeerInterest>,
+    entity_map: Res<NetworkEntityMap>,
+    mut baselines: ResMut<ReplicationBaselines>,
+    query: Query<(&NetworkId, &T, Option<&Authority>, Has<Replicated>)>,
+    mut resync_evs: EventReader<Received<ResyncReplication>>,
+) {
+    let type_registry = type_registry.read();
+    for Received { sender_id, ev } in resync_evs.read() {
+        if ev.component_type != T::type_path() {
+            continue;
+        }
+        let Some(entity) = entity_map.get_entity(&ev.entity_id) else {
+            continue;
+        };
+        let Ok((network_id, component, authority, is_replicated)) = query.get(entity) else {
+            continue;
+        };
+        let is_allowed = if is_replicated {
+            interest.is_visible(*sender_id, entity)
+        } else {
+            network_id.owner == Some(*sender_id) && authority == Some(&Authority::Peer(session.get_id()))
+        };
+        if !is_allowed {
+            continue;
+        }
+
+        // Without a baseline the component is sent whole
+        baselines.forget(*sender_id, network_id.id, T::type_path());
+        let entity_refs = get_entity_refs(component, &entity_map, |entity| !is_replicated || interest.is_visible(*sender_id, entity));
+        send_component(&session, &mut baselines, &type_registry, config.delta_compression, *sender_id, network_id.id, component, &entity_refs);
+    }
+}
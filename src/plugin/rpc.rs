@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use bevy::ecs::event::EventCursor;
+use bevy::ecs::system::SystemId;
+use bevy::prelude::*;
+use derive_more::derive::{Display, Error};
+use crate::prelude::*;
+
+/// How long `Session::request` waits for a response.
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sent back in place of a response when the handler couldn't produce one.
+#[derive(Reflect, Clone, Debug, Default)]
+pub struct RpcFailure {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Display, Error)]
+pub enum RpcError {
+    #[display("Request timed out after {timeout:?}")]
+    Timeout { timeout: Duration },
+
+    #[display("Channel was disconnected before a response arrived")]
+    Disconnected,
+
+    /// The peer received the request but failed to handle it.
+    #[display("Peer failed to handle request: {message}")]
+    Remote { message: String },
+
+    #[display("Failed to decode response as {type_path}")]
+    InvalidResponse { type_path: &'static str },
+}
+
+#[cfg(feature = "futures")]
+impl Session {
+    /// Sends `req` to `peer_id` and waits for the matching response, up to `DEFAULT_RPC_TIMEOUT`.
+    pub async fn request<Req, Resp>(&self, peer_id: Id, req: Req) -> Result<Resp, RpcError> where Req: NetworkPayload, Resp: NetworkPayload {
+        self.request_timeout(peer_id, req, DEFAULT_RPC_TIMEOUT).await
+    }
+
+    pub async fn request_timeout<Req, Resp>(&self, peer_id: Id, req: Req, timeout: Duration) -> Result<Resp, RpcError> where Req: NetworkPayload, Resp: NetworkPayload {
+        let correlation_id = Id::new();
+
+        // Subscribe before sending so the response can't arrive before there's a receiver for it
+        let mut rx = self.get_peer_channel(self.get_id());
+        self.multiplexer.send(peer_id, NetworkEvent::request(self.get_id(), req, correlation_id));
+
+        let response = with_timeout(
+            rx.recv_filtered(|ev| ev.peer_id == peer_id && ev.correlation == Some(Correlation::Response(correlation_id))),
+            timeout
+        ).await
+            .map_err(|_| RpcError::Timeout { timeout })?
+            .ok_or(RpcError::Disconnected)?;
+
+        if response.is_ev::<RpcFailure>() {
+            let message = response.get_ev::<RpcFailure>().map(|failure| failure.message).unwrap_or_default();
+            return Err(RpcError::Remote { message });
+        }
+
+        response.get_ev::<Resp>().ok_or(RpcError::InvalidResponse { type_path: Resp::type_path() })
+    }
+}
+
+#[derive(Resource)]
+struct RpcHandler<Req: NetworkPayload, Resp: NetworkPayload> {
+    system_id: SystemId<In<Received<Req>>, Resp>,
+}
+
+pub trait RpcAppExt {
+    /// Answers requests of type `Req` with the `Resp` returned by `system`.
+    /// The system receives the request along with the id of the peer that sent it.
+    fn add_rpc_handler<Req, Resp, M>(&mut self, system: impl IntoSystem<In<Received<Req>>, Resp, M> + 'static) -> &mut Self where Req: NetworkPayload, Resp: NetworkPayload;
+}
+
+impl RpcAppExt for App {
+    fn add_rpc_handler<Req, Resp, M>(&mut self, system: impl IntoSystem<In<Received<Req>>, Resp, M> + 'static) -> &mut Self where Req: NetworkPayload, Resp: NetworkPayload {
+        let system_id = self.world_mut().register_system(system);
+        self.insert_resource(RpcHandler::<Req, Resp> { system_id })
+            .add_systems(Update, handle_rpc_requests::<Req, Resp>.after(NetworkSet::Relay).run_if(resource_exists::<Session>))
+    }
+}
+
+fn handle_rpc_requests<Req: NetworkPayload, Resp: NetworkPayload>(world: &mut World, mut cursor: Local<EventCursor<NetworkEvent>>) {
+    let requests: Vec<_> = cursor.read(world.resource::<Events<NetworkEvent>>())
+        .filter_map(|ev| match ev.correlation {
+            Some(Correlation::Request(correlation_id)) if ev.is_ev::<Req>() => Some((ev.peer_id, correlation_id, ev.get_ev::<Req>())),
+            _ => None
+        })
+        .collect();
+
+    if requests.is_empty() {
+        return;
+    }
+
+    let system_id = world.resource::<RpcHandler<Req, Resp>>().system_id;
+    let session = world.resource::<Session>().clone();
+
+    for (sender_id, correlation_id, req) in requests {
+        let response = match req {
+            Some(ev) => world.run_system_with(system_id, Received { sender_id, ev })
+                .map_err(|err| err.to_string()),
+            None => Err(format!("Failed to decode request as {}", Req::type_path())),
+        };
+
+        let response = match response {
+            Ok(resp) => NetworkEvent::response(session.get_id(), resp, correlation_id),
+            Err(message) => {
+                warn!("Failed to handle {} request from {}: {}", Req::short_type_path(), sender_id, message);
+                NetworkEvent::response(session.get_id(), RpcFailure { message }, correlation_id)
+            }
+        };
+        session.get_multiplexer().send(sender_id, response);
+    }
+}
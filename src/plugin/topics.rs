@@ -0,0 +1,126 @@
+use anyhow::Result;
+use bevy::prelude::*;
+use bevy::reflect::DynamicStruct;
+use serde::{Deserialize, Serialize};
+use crate::prelude::*;
+
+/// Sent when a peer subscribes to a topic on the session's multiplexer.
+#[derive(Event, Debug, Clone)]
+pub struct TopicJoined {
+    pub topic: String,
+    pub peer_id: Id,
+}
+
+/// Sent when a peer unsubscribes from a topic on the session's multiplexer.
+#[derive(Event, Debug, Clone)]
+pub struct TopicLeft {
+    pub topic: String,
+    pub peer_id: Id,
+}
+
+/// Asks the receiving session to subscribe the sender to `topic` on its multiplexer.
+#[derive(Reflect, Clone, Debug)]
+pub struct SubscribeTopic {
+    pub topic: String,
+}
+
+/// Asks the receiving session to unsubscribe the sender from `topic` on its multiplexer.
+#[derive(Reflect, Clone, Debug)]
+pub struct UnsubscribeTopic {
+    pub topic: String,
+}
+
+/// Asks the receiving session to publish an event from the sender to every other subscriber of `topic`.
+#[derive(Reflect, Clone, Debug)]
+pub struct PublishTopic {
+    pub topic: String,
+    /// The event, postcard-encoded along with its type path.
+    pub payload: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TopicPayload(#[serde(with = "dynamic_struct_serde")] DynamicStruct);
+
+impl PublishTopic {
+    pub fn new<T>(topic: &str, ev: T) -> Result<Self> where T: Struct {
+        Ok(Self {
+            topic: topic.to_string(),
+            payload: postcard::to_allocvec(&TopicPayload(ev.clone_dynamic()))?,
+        })
+    }
+
+    pub fn get_ev(&self) -> Result<DynamicStruct> {
+        Ok(postcard::from_bytes::<TopicPayload>(&self.payload)?.0)
+    }
+}
+
+impl Session {
+    /// Subscribes this session's peer to `topic`.
+    pub fn subscribe(&self, topic: &str) {
+        self.multiplexer.subscribe(topic, self.get_id());
+    }
+
+    pub fn unsubscribe(&self, topic: &str) {
+        self.multiplexer.unsubscribe(topic, self.get_id());
+    }
+
+    /// Sends an event to every other subscriber of `topic`.
+    pub fn publish_ev<T>(&self, topic: &str, ev: T) where T: Struct {
+        self.multiplexer.publish_ev(self.get_id(), topic, ev);
+    }
+
+    /// Subscribes this session's peer to `topic` on the server at `Id::nil()`, across a bridge.
+    pub fn subscribe_remote(&self, topic: &str) {
+        self.send_ev(Id::nil(), SubscribeTopic { topic: topic.to_string() });
+    }
+
+    pub fn unsubscribe_remote(&self, topic: &str) {
+        self.send_ev(Id::nil(), UnsubscribeTopic { topic: topic.to_string() });
+    }
+
+    /// Sends an event to every other subscriber of `topic` on the server at `Id::nil()`, across a bridge.
+    pub fn publish_ev_remote<T>(&self, topic: &str, ev: T) -> Result<()> where T: Struct {
+        self.send_ev(Id::nil(), PublishTopic::new(topic, ev)?);
+        Ok(())
+    }
+}
+
+/// Applies topic requests from other peers to the session's multiplexer. Remote requests already passed
+/// the `AuthorizationPolicy` on their way in, and published events are checked again for each subscriber.
+pub fn handle_topic_requests(
+    session: Res<Session>,
+    mut subscribe_evs: EventReader<Received<SubscribeTopic>>,
+    mut unsubscribe_evs: EventReader<Received<UnsubscribeTopic>>,
+    mut publish_evs: EventReader<Received<PublishTopic>>,
+) {
+    let multiplexer = session.get_multiplexer();
+    for Received { sender_id, ev } in subscribe_evs.read() {
+        multiplexer.subscribe(&ev.topic, *sender_id);
+    }
+    for Received { sender_id, ev } in unsubscribe_evs.read() {
+        multiplexer.unsubscribe(&ev.topic, *sender_id);
+    }
+    for Received { sender_id, ev } in publish_evs.read() {
+        match ev.get_ev() {
+            Ok(payload) => multiplexer.publish_authorized(&ev.topic, NetworkEvent::new(*sender_id, payload)),
+            Err(err) => warn!("Failed to decode event published to {} by {}: {}", ev.topic, sender_id, err),
+        }
+    }
+}
+
+pub fn relay_topic_changes(
+    session: Res<Session>,
+    mut joined_evs: EventWriter<TopicJoined>,
+    mut left_evs: EventWriter<TopicLeft>,
+) {
+    for change in session.get_multiplexer().drain_topic_changes() {
+        match change {
+            TopicChange::Joined { topic, peer_id } => {
+                joined_evs.send(TopicJoined { topic, peer_id });
+            },
+            TopicChange::Left { topic, peer_id } => {
+                left_evs.send(TopicLeft { topic, peer_id });
+            },
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use anyhow::{anyhow, Result};
+use bevy::reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy::reflect::{ReflectRef, TypeRegistry};
+use bevy::prelude::*;
+use reflect_steroids::prelude::*;
+use serde::de::DeserializeSeed;
+use serde::{Deserialize, Serialize};
+use crate::prelude::*;
+
+use super::{Envelope, Frame};
+
+const JSON_TAG: u8 = 0;
+const BINARY_TAG: u8 = 1;
+
+static WIRE_TYPE_REGISTRY: LazyLock<TypeRegistry> = LazyLock::new(|| {
+    let mut registry = TypeRegistry::new();
+    registry.register_global_types();
+    registry
+});
+
+/// How frames are written to a transport. Every frame is tagged with its codec,
+/// so each side of a bridge may pick its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WireCodec {
+    /// JSON with the full type path of every payload, handy for debugging.
+    Json,
+    /// postcard, with payload types replaced by numeric ids that are defined once per connection.
+    #[default]
+    Binary,
+}
+
+/// An event as written by the binary codec.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct WireEvent {
+    recipient_id: u128,
+    sender_id: u128,
+    type_id: u32,
+    payload: Vec<u8>,
+    correlation: Option<Correlation>,
+    sequence: Option<u64>,
+}
+
+/// Encodes the frames sent over one connection.
+pub(crate) struct FrameEncoder {
+    codec: WireCodec,
+    type_ids: HashMap<&'static str, u32>,
+}
+
+impl FrameEncoder {
+    pub(crate) fn new(codec: WireCodec) -> Self {
+        Self {
+            codec,
+            type_ids: HashMap::new(),
+        }
+    }
+
+    /// Encodes `frame`, preceded by definitions for any payload types the remote hasn't seen yet.
+    pub(crate) fn encode(&mut self, frame: Frame) -> Result<Vec<Vec<u8>>> {
+        match self.codec {
+            WireCodec::Json => {
+                let mut bytes = vec![JSON_TAG];
+                serde_json::to_writer(&mut bytes, &frame)?;
+                Ok(vec![bytes])
+            },
+            WireCodec::Binary => {
+                let mut definitions = Vec::new();
+                let frame = frame.try_map_event(|envelope| self.to_wire_event(envelope, &mut definitions))?;
+
+                definitions.push(frame);
+                definitions.iter().map(|frame| {
+                    let mut bytes = vec![BINARY_TAG];
+                    bytes.extend(postcard::to_allocvec(frame)?);
+                    Ok(bytes)
+                }).collect()
+            },
+        }
+    }
+
+    fn to_wire_event(&mut self, envelope: Envelope, definitions: &mut Vec<Frame<WireEvent>>) -> Result<WireEvent> {
+        let Envelope { recipient_id, network_event } = envelope;
+
+        let type_path = network_event.ev.get_represented_type_info()
+            .ok_or_else(|| anyhow!("Can't encode a network event payload without a represented type"))?
+            .type_path();
+
+        let payload = postcard::to_allocvec(&TypedReflectSerializer::new(network_event.ev.as_partial_reflect(), &WIRE_TYPE_REGISTRY))?;
+
+        // Only defined once the payload encodes, so a failed event doesn't leave the remote without the definition
+        let next_type_id = self.type_ids.len() as u32;
+        let type_id = *self.type_ids.entry(type_path).or_insert_with(|| {
+            definitions.push(Frame::DefineType { type_id: next_type_id, type_path: type_path.to_string() });
+            next_type_id
+        });
+
+        Ok(WireEvent {
+            recipient_id: recipient_id.as_u128(),
+            sender_id: network_event.peer_id.as_u128(),
+            type_id,
+            payload,
+            correlation: network_event.correlation,
+            sequence: network_event.sequence,
+        })
+    }
+}
+
+/// Decodes the frames received over one connection.
+#[derive(Default)]
+pub(crate) struct FrameDecoder {
+    type_paths: HashMap<u32, String>,
+}
+
+impl FrameDecoder {
+    /// Returns `None` for frames that only update the decoder, such as type definitions.
+    pub(crate) fn decode(&mut self, bytes: &[u8]) -> Result<Option<Frame>> {
+        match bytes.split_first() {
+            Some((&JSON_TAG, bytes)) => Ok(Some(serde_json::from_slice(bytes)?)),
+            Some((&BINARY_TAG, bytes)) => {
+                match postcard::from_bytes::<Frame<WireEvent>>(bytes)? {
+                    Frame::DefineType { type_id, type_path } => {
+                        self.type_paths.insert(type_id, type_path);
+                        Ok(None)
+                    },
+                    frame => Ok(Some(frame.try_map_event(|wire_event| self.from_wire_event(wire_event))?)),
+                }
+            },
+            Some((tag, _)) => Err(anyhow!("Unknown frame codec tag {}", tag)),
+            None => Err(anyhow!("Received an empty frame")),
+        }
+    }
+
+    fn from_wire_event(&self, wire_event: WireEvent) -> Result<Envelope> {
+        let type_path = self.type_paths.get(&wire_event.type_id)
+            .ok_or_else(|| anyhow!("Received an event with undefined type id {}", wire_event.type_id))?;
+
+        let registration = WIRE_TYPE_REGISTRY.get_with_type_path(type_path)
+            .ok_or_else(|| anyhow!("Type {} isn't registered", type_path))?;
+
+        let mut deserializer = postcard::Deserializer::from_bytes(&wire_event.payload);
+        let value = TypedReflectDeserializer::new(registration, &WIRE_TYPE_REGISTRY).deserialize(&mut deserializer)?;
+
+        let ReflectRef::Struct(struct_ref) = value.reflect_ref() else {
+            return Err(anyhow!("Network event payload {} isn't a struct", type_path));
+        };
+
+        Ok(Envelope {
+            recipient_id: Id::from_u128(wire_event.recipient_id),
+            network_event: NetworkEvent {
+                peer_id: Id::from_u128(wire_event.sender_id),
+                ev: struct_ref.clone_dynamic(),
+                correlation: wire_event.correlation,
+                sequence: wire_event.sequence,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::reflect::GetField;
+    use super::*;
+
+    #[derive(Reflect, Clone, Debug)]
+    struct Ping {
+        n: u32,
+    }
+
+    /// Encodes an event and decodes it again, returning the number of frames it took.
+    fn round_trip(encoder: &mut FrameEncoder, decoder: &mut FrameDecoder, n: u32) -> usize {
+        let recipient_id = Id::new();
+        let sender_id = Id::new();
+        let frames = encoder.encode(Frame::Event(Envelope {
+            recipient_id,
+            network_event: NetworkEvent::new(sender_id, Ping { n }),
+        })).unwrap();
+
+        let decoded: Vec<Frame> = frames.iter().filter_map(|bytes| decoder.decode(bytes).unwrap()).collect();
+        let [Frame::Event(envelope)] = &decoded[..] else {
+            panic!("Expected a single event frame");
+        };
+        assert_eq!(envelope.recipient_id, recipient_id);
+        assert_eq!(envelope.network_event.peer_id, sender_id);
+        assert_eq!(envelope.network_event.ev.get_field::<u32>("n"), Some(&n));
+        frames.len()
+    }
+
+    #[test]
+    fn json_events_round_trip() {
+        let mut encoder = FrameEncoder::new(WireCodec::Json);
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(round_trip(&mut encoder, &mut decoder, 1), 1);
+    }
+
+    #[test]
+    fn binary_events_round_trip_and_define_their_type_once() {
+        let mut encoder = FrameEncoder::new(WireCodec::Binary);
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(round_trip(&mut encoder, &mut decoder, 1), 2);
+        assert_eq!(round_trip(&mut encoder, &mut decoder, 2), 1);
+    }
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use async_channel::{Receiver, Sender};
+use crate::prelude::*;
+
+/// An in-memory transport. Ends are created in pairs, and whatever one end sends the other receives.
+pub struct LoopbackTransport {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl LoopbackTransport {
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = async_channel::unbounded();
+        let (b_tx, b_rx) = async_channel::unbounded();
+        (
+            Self { tx: a_tx, rx: b_rx },
+            Self { tx: b_tx, rx: a_rx },
+        )
+    }
+}
+
+impl Transport for LoopbackTransport {
+    /// Since ends are created together by `pair`, connecting hands back the given end.
+    type Address = LoopbackTransport;
+
+    async fn connect(address: Self::Address) -> Result<Self> {
+        Ok(address)
+    }
+
+    async fn send(&self, frame: Vec<u8>) -> Result<()> {
+        self.tx.send(frame).await?;
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Option<Vec<u8>>> {
+        // The other end was dropped
+        Ok(self.rx.recv().await.ok())
+    }
+}
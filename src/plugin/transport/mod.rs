@@ -0,0 +1,313 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+use futures::lock::Mutex;
+use futures_util::future::{select, Either};
+use futures_util::stream::{select_all, StreamExt};
+use serde::{Deserialize, Serialize};
+use crate::prelude::*;
+
+mod codec;
+pub use codec::*;
+
+mod loopback;
+pub use loopback::*;
+
+mod reliable;
+pub use reliable::*;
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+mod tcp;
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub use tcp::*;
+
+/// A connection that carries byte frames to and from a remote multiplexer.
+/// Methods take `&self` so a bridge can send and receive at the same time.
+pub trait Transport: Send + Sync + Sized + 'static {
+    type Address: Send;
+
+    fn connect(address: Self::Address) -> impl Future<Output = Result<Self>> + Send;
+
+    fn send(&self, frame: Vec<u8>) -> impl Future<Output = Result<()>> + Send;
+
+    /// Resolves to `None` once the remote end has closed the connection.
+    fn recv(&self) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send;
+}
+
+/// Messages exchanged by two bridged multiplexers. `E` is how each codec represents events.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Frame<E = Envelope> {
+    /// Sent first by each side, listing the peer ids whose events should be forwarded to it,
+    /// the id of its `ReliableLink` if it uses one, and a token to resume its channel with.
+    Hello { peer_ids: Vec<Id>, link_id: Option<Id>, resume: Option<ResumeToken> },
+    /// Assigns a numeric id to a payload type for the rest of the connection.
+    DefineType { type_id: u32, type_path: String },
+    Event(E),
+    /// Acknowledges every event for `recipient_id` up to and including `sequence`.
+    Ack { recipient_id: Id, sequence: u64 },
+    /// Sent after the hello by a side serving an authenticated peer, so it can resume after reconnecting.
+    Resume(ResumeToken),
+}
+
+impl<E> Frame<E> {
+    pub(crate) fn try_map_event<F>(self, f: impl FnOnce(E) -> Result<F>) -> Result<Frame<F>> {
+        Ok(match self {
+            Frame::Hello { peer_ids, link_id, resume } => Frame::Hello { peer_ids, link_id, resume },
+            Frame::DefineType { type_id, type_path } => Frame::DefineType { type_id, type_path },
+            Frame::Event(ev) => Frame::Event(f(ev)?),
+            Frame::Ack { recipient_id, sequence } => Frame::Ack { recipient_id, sequence },
+            Frame::Resume(token) => Frame::Resume(token),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    pub recipient_id: Id,
+    pub network_event: NetworkEvent,
+}
+
+/// Writes an event to `transport` like `send_frame`, but skips it with a warning if it can't be encoded,
+/// since one bad payload shouldn't close the whole bridge.
+async fn send_event<T: Transport>(transport: &T, encoder: &mut FrameEncoder, envelope: Envelope) -> Result<()> {
+    let recipient_id = envelope.recipient_id;
+    let ev_name = envelope.network_event.get_ev_name();
+    let frames = match encoder.encode(Frame::Event(envelope)) {
+        Ok(frames) => frames,
+        Err(err) => {
+            warn!("Skipping network event {} to {} that can't be encoded: {}", ev_name, recipient_id, err);
+            return Ok(());
+        },
+    };
+    for bytes in frames {
+        transport.send(bytes).await?;
+    }
+    Ok(())
+}
+
+/// Writes a frame to `transport`, along with any type definitions it needs.
+async fn send_frame<T: Transport>(transport: &T, encoder: &mut FrameEncoder, frame: Frame) -> Result<()> {
+    for bytes in encoder.encode(frame)? {
+        transport.send(bytes).await?;
+    }
+    Ok(())
+}
+
+/// Reads the next frame from `transport`, or `None` once it's closed.
+async fn recv_frame<T: Transport>(transport: &T, decoder: &mut FrameDecoder) -> Result<Option<Frame>> {
+    while let Some(bytes) = transport.recv().await? {
+        if let Some(frame) = decoder.decode(&bytes)? {
+            return Ok(Some(frame));
+        }
+    }
+    Ok(None)
+}
+
+/// Options for bridging a multiplexer to a remote one.
+#[derive(Clone, Default)]
+pub struct BridgeOptions {
+    pub codec: WireCodec,
+    /// When set, events are sequenced, acknowledged and resent after a reconnect that uses the same link.
+    pub reliable: Option<ReliableLink>,
+    /// When set, the bridge offers the resume token it holds and counts the events it receives in it,
+    /// so the remote replays what never arrived. With `reliable` too, the link resends those instead.
+    pub resume: Option<ResumeHandle>,
+}
+
+impl BridgeOptions {
+    pub fn with_codec(mut self, codec: WireCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn with_reliable(mut self, link: ReliableLink) -> Self {
+        self.reliable = Some(link);
+        self
+    }
+
+    pub fn with_resume(mut self, resume: ResumeHandle) -> Self {
+        self.resume = Some(resume);
+        self
+    }
+}
+
+/// Holds the resume token issued by a remote multiplexer across reconnects of the same bridge.
+#[derive(Clone, Default)]
+pub struct ResumeHandle(Arc<std::sync::Mutex<Option<ResumeToken>>>);
+
+impl ResumeHandle {
+    pub fn get(&self) -> Option<ResumeToken> {
+        *self.0.lock().unwrap()
+    }
+
+    fn set(&self, token: ResumeToken) {
+        *self.0.lock().unwrap() = Some(token);
+    }
+
+    /// Counts an event received for `recipient_id`, so a resume replays from the one after it.
+    fn advance(&self, recipient_id: Id) {
+        if let Some(token) = self.0.lock().unwrap().as_mut().filter(|token| token.peer_id == recipient_id) {
+            token.last_index = token.last_index.map(|last_index| last_index + 1);
+        }
+    }
+}
+
+/// Connects `multiplexer` to a remote one over `transport` until either side closes it.
+///
+/// `local_ids` are the peers served by this multiplexer. Events the remote side sends to them are
+/// delivered locally, and events sent locally to the peers the remote announces are forwarded to it.
+pub async fn bridge<T: Transport>(multiplexer: Multiplexer, transport: T, local_ids: Vec<Id>, mut options: BridgeOptions) -> Result<()> {
+    let mut encoder = FrameEncoder::new(options.codec);
+    let mut decoder = FrameDecoder::default();
+
+    let Some((remote_ids, _)) = exchange_hello(&transport, &mut encoder, &mut decoder, local_ids, &mut options).await? else {
+        return Ok(());
+    };
+
+    let channels = remote_ids.into_iter().map(|remote_id| multiplexer.get_channel(remote_id)).collect();
+    forward(multiplexer, transport, encoder, decoder, channels, None, options).await
+}
+
+/// Like `bridge`, but for a remote peer whose id was already established, e.g. by authenticating it.
+/// The peer ids announced by the remote are ignored, only `peer_id`'s events are forwarded to it,
+/// and every event it sends is stamped with `peer_id` as the sender. If the peer offers a token for
+/// a channel still within its grace period, forwarding resumes from the last event it received.
+/// With a reliable link, the link resends those it never acknowledged instead.
+pub async fn bridge_peer<T: Transport>(multiplexer: Multiplexer, transport: T, local_ids: Vec<Id>, peer_id: Id, mut options: BridgeOptions) -> Result<()> {
+    let mut encoder = FrameEncoder::new(options.codec);
+    let mut decoder = FrameDecoder::default();
+
+    let Some((_, resume)) = exchange_hello(&transport, &mut encoder, &mut decoder, local_ids, &mut options).await? else {
+        return Ok(());
+    };
+
+    let channel = resume
+        .filter(|token| token.peer_id == peer_id)
+        // Replaying what the link resends anyway would deliver it twice
+        .map(|token| ResumeToken { last_index: token.last_index.filter(|_| options.reliable.is_none()), ..token })
+        .and_then(|token| multiplexer.resume_channel(&token))
+        .unwrap_or_else(|| multiplexer.get_channel(peer_id));
+    send_frame(&transport, &mut encoder, Frame::Resume(channel.resume_token())).await?;
+
+    forward(multiplexer, transport, encoder, decoder, vec![channel], Some(peer_id), options).await
+}
+
+/// Announces `local_ids` and returns the ids and resume token announced by the remote,
+/// or `None` if it closed the connection. Reliable delivery is only used if both sides announce
+/// a link, so `options.reliable` is cleared if the remote doesn't.
+async fn exchange_hello<T: Transport>(transport: &T, encoder: &mut FrameEncoder, decoder: &mut FrameDecoder, local_ids: Vec<Id>, options: &mut BridgeOptions) -> Result<Option<(Vec<Id>, Option<ResumeToken>)>> {
+    send_frame(transport, encoder, Frame::Hello {
+        peer_ids: local_ids,
+        link_id: options.reliable.as_ref().map(|link| link.get_id()),
+        resume: options.resume.as_ref().and_then(|resume| resume.get()),
+    }).await?;
+
+    match recv_frame(transport, decoder).await? {
+        Some(Frame::Hello { peer_ids, link_id, resume }) => {
+            if let Some(link) = &options.reliable {
+                link.connect(link_id);
+            }
+            if link_id.is_none() {
+                options.reliable = None;
+            }
+            Ok(Some((peer_ids, resume)))
+        },
+        Some(_) => Err(anyhow!("Expected a hello frame from the remote multiplexer")),
+        None => Ok(None),
+    }
+}
+
+/// Forwards events from `channels` to the remote and delivers events from the remote locally.
+/// The channels are created by the caller before forwarding starts, so nothing sent in between is missed.
+async fn forward<T: Transport>(multiplexer: Multiplexer, transport: T, encoder: FrameEncoder, mut decoder: FrameDecoder, channels: Vec<Channel>, sender_id: Option<Id>, options: BridgeOptions) -> Result<()> {
+    let BridgeOptions { reliable: link, resume, .. } = options;
+
+    let remote_ids: Vec<Id> = channels.iter().map(|channel| channel.get_id()).collect();
+    let mut remote_channels = select_all(channels.into_iter().map(|channel| {
+        let remote_id = channel.get_id();
+        channel.map(move |ev| (remote_id, ev))
+    }));
+
+    // Shared by both directions, since acks are sent while receiving
+    let encoder = Mutex::new(encoder);
+
+    let outgoing = async {
+        // Resend whatever the remote hadn't acknowledged when the last connection dropped
+        if let Some(link) = &link {
+            for recipient_id in &remote_ids {
+                for network_event in link.unacked(*recipient_id) {
+                    send_event(&transport, &mut *encoder.lock().await, Envelope { recipient_id: *recipient_id, network_event }).await?;
+                }
+            }
+        }
+
+        while let Some((recipient_id, mut network_event)) = remote_channels.next().await {
+            if let Some(link) = &link {
+                network_event = link.stamp(recipient_id, network_event);
+            }
+            send_event(&transport, &mut *encoder.lock().await, Envelope { recipient_id, network_event }).await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let incoming = async {
+        while let Some(frame) = recv_frame(&transport, &mut decoder).await? {
+            match frame {
+                // Don't echo events back to the side they came from
+                Frame::Event(Envelope { recipient_id, .. }) if remote_ids.contains(&recipient_id) => {},
+                Frame::Event(Envelope { recipient_id, mut network_event }) => {
+                    if let Some(link) = &link && let Some(sequence) = network_event.sequence {
+                        let is_new = link.receive(recipient_id, sequence);
+                        // Ack duplicates too, since the remote resends until it gets one
+                        send_frame(&transport, &mut *encoder.lock().await, Frame::Ack { recipient_id, sequence }).await?;
+                        if !is_new {
+                            // Already delivered before a reconnect
+                            continue;
+                        }
+                    }
+                    if let Some(resume) = &resume {
+                        resume.advance(recipient_id);
+                    }
+
+                    if let Some(sender_id) = sender_id {
+                        network_event.peer_id = sender_id;
+                    } else if !remote_ids.contains(&network_event.peer_id) {
+                        // Rate limits and authorization go by the sender, so it has to be one the remote announced
+                        multiplexer.reject(recipient_id, &network_event, RejectReason::Unauthorized);
+                        continue;
+                    }
+                    multiplexer.send_remote(recipient_id, network_event).await?;
+                },
+                Frame::Ack { recipient_id, sequence } => {
+                    if let Some(link) = &link {
+                        link.ack(recipient_id, sequence);
+                    }
+                },
+                Frame::Resume(token) => {
+                    if let Some(resume) = &resume {
+                        resume.set(token);
+                    }
+                },
+                Frame::Hello { .. } => warn!("Ignoring repeated hello frame from remote multiplexer."),
+                Frame::DefineType { .. } => {},
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    match select(pin!(outgoing), pin!(incoming)).await {
+        Either::Left((result, _)) => result,
+        Either::Right((result, _)) => result,
+    }
+}
+
+impl Session {
+    /// Bridges this session's multiplexer to a remote one, so events to and from peers in the
+    /// other process go through the usual `send_ev`/`PeerEvent` paths.
+    pub fn bridge<T: Transport>(&self, transport: T, options: BridgeOptions) -> impl Future<Output = Result<()>> + Send + 'static {
+        bridge(self.get_multiplexer(), transport, vec![self.get_id()], options)
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Delivery state for a reliable bridge, kept across reconnects.
+///
+/// Each event forwarded to a remote peer is stamped with the next sequence number for that peer and
+/// kept until the remote acknowledges it. Acks are cumulative, like a `MultiplexerChannel`'s `last_ev`
+/// cursor, so one ack releases every event up to its sequence number. When a bridge reconnects with
+/// the same link, unacknowledged events are resent first, and the receiving side drops any it already has.
+/// At most `capacity` events are kept per peer. Past that the oldest are dropped and won't be resent.
+#[derive(Clone)]
+pub struct ReliableLink {
+    state: Arc<Mutex<ReliableState>>,
+}
+
+struct ReliableState {
+    /// Identifies this link, so the remote can tell a reconnect from a restarted process.
+    link_id: Id,
+    capacity: usize,
+    next_sequence: HashMap<Id, u64>,
+    unacked: HashMap<Id, VecDeque<NetworkEvent>>,
+    /// Highest sequence number received per recipient from the current remote link.
+    received: HashMap<Id, u64>,
+    remote_link_id: Option<Id>,
+}
+
+impl Default for ReliableLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReliableLink {
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    pub fn new() -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ReliableState {
+                link_id: Id::new(),
+                capacity,
+                next_sequence: HashMap::new(),
+                unacked: HashMap::new(),
+                received: HashMap::new(),
+                remote_link_id: None,
+            }))
+        }
+    }
+
+    pub fn get_id(&self) -> Id {
+        self.state.lock().unwrap().link_id
+    }
+
+    /// Called when a bridge connects. If the remote is a different link than last time,
+    /// its sequence numbers start over, so what was received from the previous one is forgotten.
+    /// A remote without a link never acknowledges anything, so nothing is kept for it either.
+    pub(crate) fn connect(&self, remote_link_id: Option<Id>) {
+        let mut state = self.state.lock().unwrap();
+        if state.remote_link_id != remote_link_id {
+            state.received.clear();
+            state.remote_link_id = remote_link_id;
+        }
+        if remote_link_id.is_none() {
+            state.unacked.clear();
+        }
+    }
+
+    /// Stamps `ev` with the next sequence number for `recipient_id` and keeps it until it's acknowledged.
+    pub(crate) fn stamp(&self, recipient_id: Id, mut ev: NetworkEvent) -> NetworkEvent {
+        let mut state = self.state.lock().unwrap();
+
+        let sequence = state.next_sequence.entry(recipient_id).or_default();
+        *sequence += 1;
+        ev.sequence = Some(*sequence);
+
+        let capacity = state.capacity;
+        let unacked = state.unacked.entry(recipient_id).or_default();
+        if unacked.len() >= capacity {
+            warn!("Reliable link to {} has {} unacknowledged events, dropping the oldest.", recipient_id, capacity);
+            unacked.pop_front();
+        }
+        unacked.push_back(ev.clone());
+        ev
+    }
+
+    /// Events sent to `recipient_id` that haven't been acknowledged yet, oldest first.
+    pub(crate) fn unacked(&self, recipient_id: Id) -> Vec<NetworkEvent> {
+        let state = self.state.lock().unwrap();
+        state.unacked.get(&recipient_id).map(|evs| evs.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Releases every event sent to `recipient_id` up to and including `sequence`.
+    pub(crate) fn ack(&self, recipient_id: Id, sequence: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(unacked) = state.unacked.get_mut(&recipient_id) {
+            while unacked.front().is_some_and(|ev| ev.sequence.is_some_and(|ev_sequence| ev_sequence <= sequence)) {
+                unacked.pop_front();
+            }
+        }
+    }
+
+    /// Records an event with `sequence` received for `recipient_id`. Returns `false` if it's
+    /// a duplicate and should be dropped.
+    pub(crate) fn receive(&self, recipient_id: Id, sequence: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let received = state.received.entry(recipient_id).or_default();
+        if sequence <= *received {
+            return false;
+        }
+        *received = sequence;
+        true
+    }
+}
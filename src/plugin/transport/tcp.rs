@@ -0,0 +1,88 @@
+use std::io::ErrorKind;
+
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+use futures::lock::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use crate::prelude::*;
+
+/// Largest frame accepted from a remote, so a corrupt length prefix can't allocate unbounded memory.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Sends frames over TCP, each prefixed with its length as a big-endian `u32`.
+pub struct TcpTransport {
+    reader: Mutex<OwnedReadHalf>,
+    writer: Mutex<OwnedWriteHalf>,
+}
+
+impl TcpTransport {
+    pub fn from_stream(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    type Address = String;
+
+    async fn connect(address: Self::Address) -> Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        stream.set_nodelay(true)?;
+        Ok(Self::from_stream(stream))
+    }
+
+    async fn send(&self, frame: Vec<u8>) -> Result<()> {
+        if frame.len() > MAX_FRAME_LEN {
+            return Err(anyhow!("Frame of {} bytes exceeds the maximum of {}", frame.len(), MAX_FRAME_LEN));
+        }
+
+        let mut writer = self.writer.lock().await;
+        writer.write_u32(frame.len() as u32).await?;
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Option<Vec<u8>>> {
+        let mut reader = self.reader.lock().await;
+
+        let len = match reader.read_u32().await {
+            Ok(len) => len as usize,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        if len > MAX_FRAME_LEN {
+            return Err(anyhow!("Frame of {} bytes exceeds the maximum of {}", len, MAX_FRAME_LEN));
+        }
+
+        let mut frame = vec![0; len];
+        reader.read_exact(&mut frame).await?;
+        Ok(Some(frame))
+    }
+}
+
+/// Accepts TCP connections and bridges each one to `multiplexer`, serving `local_ids` to the remote side.
+/// A `ReliableLink` in `options` is shared by every accepted connection, so only use one with a single remote.
+pub async fn listen_tcp(multiplexer: Multiplexer, address: impl ToSocketAddrs, local_ids: Vec<Id>, options: BridgeOptions) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+
+    loop {
+        let (stream, remote_address) = listener.accept().await?;
+        stream.set_nodelay(true)?;
+
+        let multiplexer = multiplexer.clone();
+        let local_ids = local_ids.clone();
+        let options = options.clone();
+        tokio::spawn(async move {
+            if let Err(err) = bridge(multiplexer, TcpTransport::from_stream(stream), local_ids, options).await {
+                warn!("Bridge to {} closed with an error: {}", remote_address, err);
+            }
+        });
+    }
+}
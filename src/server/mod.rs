@@ -1,6 +1,11 @@
 //mod axum;
 //pub use axum::*;
 
+#[cfg(all(feature = "http_server", feature = "futures", not(target_arch = "wasm32")))]
+mod websocket;
+#[cfg(all(feature = "http_server", feature = "futures", not(target_arch = "wasm32")))]
+pub use websocket::*;
+
 use bevy::prelude::*;
 use crate::prelude::*;
 
@@ -16,6 +21,7 @@ impl FluxServerPlugin {
 
 impl Plugin for FluxServerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((FluxPlugin::new(self.config.clone())));
+        app.add_plugins((FluxPlugin::new(self.config.clone())))
+            .add_systems(Update, handle_topic_requests.after(NetworkSet::Relay).run_if(resource_exists::<Session>));
     }
 }
\ No newline at end of file
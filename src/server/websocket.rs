@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use bevy::prelude::*;
+use futures::lock::Mutex;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use crate::prelude::*;
+
+/// Decides which peer a WebSocket connection belongs to from its request headers and query parameters.
+/// Returning `None` rejects the connection.
+pub type AuthenticatePeer = Arc<dyn Fn(&HeaderMap, &HashMap<String, String>) -> Option<Id> + Send + Sync>;
+
+#[derive(Clone)]
+struct WsState {
+    multiplexer: Multiplexer,
+    local_ids: Vec<Id>,
+    authenticate: AuthenticatePeer,
+    codec: WireCodec,
+    /// One link per authenticated peer, so a reconnecting peer gets what it missed. `None` if reliability is off.
+    /// A link is dropped once its peer's channel is freed, after the grace period or once its mailbox is empty.
+    links: Option<Arc<std::sync::Mutex<HashMap<Id, ReliableLink>>>>,
+}
+
+/// Creates a router with a `/ws` route that bridges authenticated peers to `multiplexer`.
+/// `FluxServerPlugin` doesn't serve HTTP itself, so merge this into the app's axum router and serve that,
+/// e.g. `app_router.merge(ws_router(session.get_multiplexer(), authenticate, WireCodec::Binary, true))`.
+/// Peers can send to the server at `Id::nil()`.
+/// With `reliable`, each peer keeps a `ReliableLink` across reconnects.
+pub fn ws_router(multiplexer: Multiplexer, authenticate: AuthenticatePeer, codec: WireCodec, reliable: bool) -> Router {
+    Router::new()
+        .route("/ws", get(ws_handler))
+        .with_state(WsState {
+            multiplexer,
+            local_ids: vec![Id::nil()],
+            authenticate,
+            codec,
+            links: reliable.then(Default::default),
+        })
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<WsState>,
+) -> Response {
+    let Some(peer_id) = (state.authenticate)(&headers, &params) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let options = BridgeOptions {
+        codec: state.codec,
+        reliable: state.links.as_ref().map(|links| {
+            let mut links = links.lock().unwrap();
+            links.retain(|link_peer_id, _| *link_peer_id == peer_id || state.multiplexer.has_channel(*link_peer_id));
+            links.entry(peer_id).or_default().clone()
+        }),
+        ..default()
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        if let Err(err) = bridge_peer(state.multiplexer, WsTransport::from_socket(socket), state.local_ids, peer_id, options).await {
+            warn!("WebSocket bridge for peer {} closed with an error: {}", peer_id, err);
+        }
+    })
+}
+
+/// The server's end of a WebSocket accepted by axum.
+pub struct WsTransport {
+    sink: Mutex<SplitSink<WebSocket, Message>>,
+    stream: Mutex<SplitStream<WebSocket>>,
+}
+
+impl WsTransport {
+    pub fn from_socket(socket: WebSocket) -> Self {
+        let (sink, stream) = socket.split();
+        Self {
+            sink: Mutex::new(sink),
+            stream: Mutex::new(stream),
+        }
+    }
+}
+
+impl Transport for WsTransport {
+    /// Sockets are accepted by axum, so connecting wraps one that's already open.
+    type Address = WebSocket;
+
+    async fn connect(address: Self::Address) -> Result<Self> {
+        Ok(Self::from_socket(address))
+    }
+
+    async fn send(&self, frame: Vec<u8>) -> Result<()> {
+        self.sink.lock().await.send(Message::Binary(frame.into())).await?;
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Option<Vec<u8>>> {
+        let mut stream = self.stream.lock().await;
+        while let Some(message) = stream.next().await {
+            match message? {
+                Message::Binary(data) => return Ok(Some(data.to_vec())),
+                Message::Text(text) => return Ok(Some(text.as_bytes().to_vec())),
+                Message::Close(_) => return Ok(None),
+                // Pings are answered by axum
+                Message::Ping(_) | Message::Pong(_) => {},
+            }
+        }
+        Ok(None)
+    }
+}
@@ -44,9 +44,20 @@ impl FluxConfig {
         format!("https://{}:{}", self.get_api_hostname(), self.get_server_port())
     }
 
+    /// The URL of the server's WebSocket route.
+    pub fn get_ws_url(&self) -> String {
+        format!("wss://{}:{}/ws", self.get_api_hostname(), self.get_server_port())
+    }
+
     pub fn get_api_origin(&self) -> String {
         format!("https://{}", self.get_api_hostname())
     }
 }
 
-
+/// Controls how many network events `relay_network_events` forwards each frame.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct NetworkRelayConfig {
+    /// Maximum number of events relayed per frame. Events over the budget stay queued, in order, for the next frame.
+    /// `None` drains every pending event.
+    pub max_events_per_frame: Option<usize>,
+}
@@ -219,21 +219,65 @@ pub struct NetworkEvent {
     pub peer_id: Id,
     #[clone(clone_with = "DynamicStruct::clone_dynamic")]
     #[serde(with = "dynamic_struct_serde")]
-    pub ev: DynamicStruct
+    pub ev: DynamicStruct,
+    /// Set on request/response events so a response can be matched to the request it answers.
+    #[serde(default)]
+    pub correlation: Option<Correlation>,
+    /// Stamped by reliable bridges so the receiving side can acknowledge events and drop duplicates.
+    #[serde(default)]
+    pub sequence: Option<u64>
+}
+
+/// Links a response to the request it answers.
+#[cfg_attr(feature = "bevy", derive(Reflect))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Correlation {
+    Request(Id),
+    Response(Id)
 }
 
 impl NetworkEvent {
     pub fn new<T>(peer_id: Id, ev: T) -> Self where T: Struct {
         Self {
             peer_id,
-            ev: ev.clone_dynamic()
+            ev: ev.clone_dynamic(),
+            correlation: None,
+            sequence: None
+        }
+    }
+
+    pub fn request<T>(peer_id: Id, ev: T, correlation_id: Id) -> Self where T: Struct {
+        Self {
+            correlation: Some(Correlation::Request(correlation_id)),
+            ..Self::new(peer_id, ev)
+        }
+    }
+
+    pub fn response<T>(peer_id: Id, ev: T, correlation_id: Id) -> Self where T: Struct {
+        Self {
+            correlation: Some(Correlation::Response(correlation_id)),
+            ..Self::new(peer_id, ev)
         }
     }
 
+    pub fn is_response(&self) -> bool {
+        matches!(self.correlation, Some(Correlation::Response(_)))
+    }
+
     pub fn get_ev<T>(&self) -> Option<T> where T: FromDynamic {
         T::from_dynamic(&self.ev)
     }
 
+    /// Whether the payload represents type `T`, without converting it.
+    pub fn is_ev<T>(&self) -> bool where T: Typed {
+        self.ev.get_represented_type_info().is_some_and(|type_info| type_info.type_path() == T::type_path())
+    }
+
+    /// Type path of the payload, if it represents a known type.
+    pub fn type_path(&self) -> Option<&'static str> {
+        self.ev.get_represented_type_info().map(|type_info| type_info.type_path())
+    }
+
     pub fn get_ev_name(&self) -> String {
         match self.ev.get_represented_type_info() {
             Some(type_info) => {
@@ -265,6 +309,14 @@ impl Id {
         Self { id: Uuid::from_str(text).unwrap() }
     }
 
+    pub fn as_u128(&self) -> u128 {
+        self.id.as_u128()
+    }
+
+    pub fn from_u128(value: u128) -> Self {
+        Self { id: Uuid::from_u128(value) }
+    }
+
     pub fn to_pretty_string(&self) -> String {
         let mut pretty_string = self.id.to_string();
         pretty_string.remove_matches("-");